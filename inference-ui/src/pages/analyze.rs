@@ -10,6 +10,18 @@ pub struct PredictResponse {
     pub probabilities: [f64; 2],
 }
 
+/// Subset of the `/explain` response used by the page
+#[derive(Clone, PartialEq, serde::Deserialize, Debug)]
+pub struct ExplainResponse {
+    pub class: String,
+    pub method: String,
+    /// Base64 PNG of the Grad-CAM colormap at the uploaded image size
+    pub overlay_png: String,
+}
+
+/// CSS gradient matching the server's jet colormap (low -> high attention)
+const HEATMAP_GRADIENT: &str = "linear-gradient(to right, #000080, #0000ff, #00ffff, #ffff00, #ff0000, #800000)";
+
 fn api_base() -> String {
     let w = window().unwrap();
     // 1) If hosted on Render, point to the Render API domain
@@ -27,6 +39,28 @@ fn api_base() -> String {
     "http://localhost:8080".to_string()
 }
 
+/// Posts the file as the multipart `image` field and returns the response body
+async fn post_image(path: &str, f: &WebFile) -> Result<String, String> {
+    let form = FormData::new().unwrap();
+    form.append_with_blob_and_filename("image", f, &f.name()).ok();
+    let init = web_sys::RequestInit::new();
+    init.set_method("POST");
+    init.set_body(&form.into());
+    let url = format!("{}{}", api_base(), path);
+    let request = web_sys::Request::new_with_str_and_init(&url, &init).unwrap();
+    let win = window().unwrap();
+    let resp = wasm_bindgen_futures::JsFuture::from(win.fetch_with_request(&request)).await
+        .map_err(|_| "Network error".to_string())?;
+    let resp: web_sys::Response = resp.dyn_into().unwrap();
+    let text = wasm_bindgen_futures::JsFuture::from(resp.text().unwrap()).await
+        .ok().and_then(|t| t.as_string());
+    match (resp.ok(), text) {
+        (true, Some(text)) => Ok(text),
+        (false, Some(text)) if !text.is_empty() => Err(text),
+        _ => Err(format!("Request to {} failed", path)),
+    }
+}
+
 #[function_component(AnalyzePage)]
 pub fn analyze_page() -> Html {
     let server_ok = use_state(|| None as Option<bool>);
@@ -35,6 +69,10 @@ pub fn analyze_page() -> Html {
     let loading = use_state(|| false);
     let error = use_state(|| None as Option<String>);
     let result = use_state(|| None as Option<PredictResponse>);
+    let explanation = use_state(|| None as Option<ExplainResponse>);
+    let explain_error = use_state(|| None as Option<String>);
+    let show_heatmap = use_state(|| true);
+    let heatmap_opacity = use_state(|| 0.5_f64);
 
     {
         let server_ok = server_ok.clone();
@@ -56,12 +94,16 @@ pub fn analyze_page() -> Html {
         let preview_url_state = preview_url.clone();
         let result = result.clone();
         let error_state = error.clone();
+        let explanation = explanation.clone();
+        let explain_error = explain_error.clone();
         Callback::from(move |e: Event| {
             let input: HtmlInputElement = e.target_unchecked_into();
             if let Some(list) = input.files() {
                 if let Some(f) = list.get(0) {
                     result.set(None);
                     error_state.set(None);
+                    explanation.set(None);
+                    explain_error.set(None);
                     if let Some(prev) = (*preview_url_state).clone() {
                         let _ = Url::revoke_object_url(&prev);
                     }
@@ -78,6 +120,8 @@ pub fn analyze_page() -> Html {
         let preview_url_state = preview_url.clone();
         let result = result.clone();
         let error_state = error.clone();
+        let explanation = explanation.clone();
+        let explain_error = explain_error.clone();
         Callback::from(move |e: DragEvent| {
             e.prevent_default();
             if let Some(dt) = e.data_transfer() {
@@ -85,6 +129,8 @@ pub fn analyze_page() -> Html {
                     if let Some(f) = list.get(0) {
                         result.set(None);
                         error_state.set(None);
+                        explanation.set(None);
+                        explain_error.set(None);
                         if let Some(prev) = (*preview_url_state).clone() {
                             let _ = Url::revoke_object_url(&prev);
                         }
@@ -103,51 +149,59 @@ pub fn analyze_page() -> Html {
         let loading_outer = loading.clone();
         let error_outer = error.clone();
         let result_outer = result.clone();
+        let explanation_outer = explanation.clone();
+        let explain_error_outer = explain_error.clone();
         let file_outer = file.clone();
         Callback::from(move |_| {
             let loading = loading_outer.clone();
             let error_state = error_outer.clone();
             let result_state = result_outer.clone();
+            let explanation_state = explanation_outer.clone();
+            let explain_error_state = explain_error_outer.clone();
             let file_state = file_outer.clone();
             if file_state.is_none() { return; }
             let f = (*file_state).clone().unwrap();
             loading.set(true);
             error_state.set(None);
             result_state.set(None);
+            explanation_state.set(None);
+            explain_error_state.set(None);
             spawn_local(async move {
-                let form = FormData::new().unwrap();
-                form.append_with_blob_and_filename("image", &f, &f.name()).ok();
-                let init = web_sys::RequestInit::new();
-                init.set_method("POST");
-                init.set_body(&form.into());
-                let url = format!("{}/predict", api_base());
-                let request = web_sys::Request::new_with_str_and_init(&url, &init).unwrap();
-                let win = window().unwrap();
-                match wasm_bindgen_futures::JsFuture::from(win.fetch_with_request(&request)).await {
-                    Ok(resp) => {
-                        let resp: web_sys::Response = resp.dyn_into().unwrap();
-                        if !resp.ok() {
-                            let text = wasm_bindgen_futures::JsFuture::from(resp.text().unwrap()).await
-                                .ok().and_then(|t| t.as_string()).unwrap_or_else(|| "Predict failed".to_string());
-                            error_state.set(Some(text));
-                        } else {
-                            let text_js = wasm_bindgen_futures::JsFuture::from(resp.text().unwrap()).await;
-                            match text_js.and_then(|t| Ok(t.as_string().unwrap_or_default())) {
-                                Ok(text) => match serde_json::from_str::<PredictResponse>(&text) {
-                                    Ok(parsed) => result_state.set(Some(parsed)),
-                                    Err(e) => error_state.set(Some(format!("Invalid JSON: {}", e))),
-                                },
-                                Err(_) => error_state.set(Some("Predict failed".into())),
-                            }
-                        }
-                    }
-                    Err(_) => error_state.set(Some("Network error".into())),
+                match post_image("/predict", &f).await {
+                    Ok(text) => match serde_json::from_str::<PredictResponse>(&text) {
+                        Ok(parsed) => result_state.set(Some(parsed)),
+                        Err(e) => error_state.set(Some(format!("Invalid JSON: {}", e))),
+                    },
+                    Err(e) => error_state.set(Some(e)),
+                }
+                // alpha=1 returns the bare colormap; opacity is applied client-side
+                match post_image("/explain?alpha=1", &f).await {
+                    Ok(text) => match serde_json::from_str::<ExplainResponse>(&text) {
+                        Ok(parsed) => explanation_state.set(Some(parsed)),
+                        Err(e) => explain_error_state.set(Some(format!("Invalid JSON: {}", e))),
+                    },
+                    Err(e) => explain_error_state.set(Some(e)),
                 }
                 loading.set(false);
             });
         })
     };
 
+    let on_toggle_heatmap = {
+        let show_heatmap = show_heatmap.clone();
+        Callback::from(move |_| show_heatmap.set(!*show_heatmap))
+    };
+
+    let on_opacity_input = {
+        let heatmap_opacity = heatmap_opacity.clone();
+        Callback::from(move |e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            if let Ok(v) = input.value().parse::<f64>() {
+                heatmap_opacity.set((v / 100.0).clamp(0.0, 1.0));
+            }
+        })
+    };
+
     let server_label = match *server_ok {
         None => ("badge badge-wait", "checking..."),
         Some(true) => ("badge badge-ok", "online"),
@@ -183,7 +237,17 @@ pub fn analyze_page() -> Html {
                     </div>
                     <div class="flex-1 min-w-[280px]">
                         if let Some(url) = (*preview_url).clone() {
-                            <img class="max-h-80 w-full object-contain border border-white/10 rounded-lg" src={url} alt="preview" />
+                            <div class="relative">
+                                <img class="max-h-80 w-full object-contain border border-white/10 rounded-lg" src={url} alt="preview" />
+                                if let (Some(exp), true) = ((*explanation).clone(), *show_heatmap) {
+                                    <img
+                                        class="absolute inset-0 h-full w-full object-contain pointer-events-none rounded-lg"
+                                        src={format!("data:image/png;base64,{}", exp.overlay_png)}
+                                        style={format!("opacity:{}", *heatmap_opacity)}
+                                        alt="attention heatmap"
+                                    />
+                                }
+                            </div>
                         } else {
                             <div class="border border-dashed border-white/30 rounded-xl h-48 flex items-center justify-center opacity-80">
                                 <span class="text-sm opacity-80">{"No image selected"}</span>
@@ -225,6 +289,36 @@ pub fn analyze_page() -> Html {
                         </div>
                     </div>
                 }
+                if let Some(exp) = (*explanation).clone() {
+                    <div class="mt-4 pt-4 border-t border-white/10">
+                        <div class="flex flex-wrap items-center gap-4">
+                            <label class="flex items-center gap-2 text-sm cursor-pointer">
+                                <input type="checkbox" checked={*show_heatmap} onchange={on_toggle_heatmap} />
+                                {format!("Show attention heatmap ({} for {})", exp.method, exp.class)}
+                            </label>
+                            <label class="flex items-center gap-2 text-xs opacity-80">
+                                {"Opacity"}
+                                <input
+                                    type="range" min="0" max="100"
+                                    value={((*heatmap_opacity) * 100.0).round().to_string()}
+                                    disabled={!*show_heatmap}
+                                    oninput={on_opacity_input}
+                                />
+                                <span>{format!("{}%", ((*heatmap_opacity) * 100.0).round() as i32)}</span>
+                            </label>
+                        </div>
+                        <div class="mt-3 max-w-xs">
+                            <div class="h-2 rounded" style={format!("background:{}", HEATMAP_GRADIENT)} />
+                            <div class="flex justify-between text-[11px] opacity-70 mt-1">
+                                <span>{"Low attention"}</span>
+                                <span>{"High attention"}</span>
+                            </div>
+                        </div>
+                    </div>
+                }
+                if let Some(err) = (*explain_error).clone() {
+                    <p class="text-xs opacity-70 mt-3">{format!("Heatmap unavailable: {}", err)}</p>
+                }
             </div>
         </div>
     }
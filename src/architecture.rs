@@ -0,0 +1,411 @@
+//! Convolutional stack described as a list of blocks, or a reference backbone
+//!
+//! Shared by the training model and the inference module (included with `#[path]`),
+//! so both build exactly the same layers from a `ModelConfig`.
+
+use anyhow::{bail, ensure, Result};
+use burn::{
+    module::{Ignored, Module, Param, RunningState},
+    nn::{
+        conv::{Conv2d, Conv2dConfig, Conv2dRecord},
+        BatchNorm, BatchNormConfig, BatchNormRecord, GroupNorm, GroupNormConfig, PaddingConfig2d,
+    },
+    tensor::{
+        activation::{gelu, relu, silu},
+        backend::Backend,
+        module::{avg_pool2d, max_pool2d},
+        Int, Tensor,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::reference::{mobilenet_v2_out_channels, resnet18_out_channels, MobileNetV2, ResNet18};
+
+/// Feature extractor placed before the classifier head
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Architecture {
+    /// The `conv_blocks` list, followed by 4x4 adaptive pooling
+    #[default]
+    Custom,
+    /// ResNet-18 with stage widths `base_width` × 1, 2, 4, 8, followed by global pooling
+    #[serde(rename = "resnet18")]
+    ResNet18 { base_width: usize },
+    /// MobileNetV2 with channels scaled by `width_multiplier`, followed by global pooling
+    #[serde(rename = "mobilenet_v2")]
+    MobileNetV2 { width_multiplier: f32 },
+}
+
+impl Architecture {
+    /// Channels of the feature map fed to the head
+    pub fn out_channels(&self, in_channels: usize, conv_blocks: &[ConvBlockConfig]) -> usize {
+        match *self {
+            Self::Custom => conv_blocks.last().map_or(in_channels, |b| b.filters),
+            Self::ResNet18 { base_width } => resnet18_out_channels(base_width),
+            Self::MobileNetV2 { width_multiplier } => mobilenet_v2_out_channels(width_multiplier),
+        }
+    }
+
+    /// Side of the adaptive pooling output before flattening
+    pub fn pooled_size(&self) -> usize {
+        match self {
+            Self::Custom => 4,
+            Self::ResNet18 { .. } | Self::MobileNetV2 { .. } => 1,
+        }
+    }
+}
+
+/// One convolutional block: conv -> norm -> (+ shortcut) -> activation -> pooling
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConvBlockConfig {
+    /// Output channels
+    pub filters: usize,
+    /// Square kernel size; padding keeps the spatial size at stride 1
+    pub kernel_size: usize,
+    pub stride: usize,
+    pub norm: Normalization,
+    pub activation: Activation,
+    pub pooling: Pooling,
+    /// Adds the block input back before the activation, through a 1x1 projection
+    /// when the channel count or stride changes
+    pub residual: bool,
+}
+
+impl ConvBlockConfig {
+    /// 3x3 conv, BatchNorm, ReLU and 2x2 max pooling
+    pub fn new(filters: usize) -> Self {
+        Self {
+            filters,
+            kernel_size: 3,
+            stride: 1,
+            norm: Normalization::BatchNorm,
+            activation: Activation::Relu,
+            pooling: Pooling::Max { size: 2 },
+            residual: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Normalization {
+    BatchNorm,
+    /// Channels split into `groups` groups; must divide `filters`
+    GroupNorm { groups: usize },
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Activation {
+    Relu,
+    Gelu,
+    Silu,
+}
+
+impl Activation {
+    pub fn forward<B: Backend, const D: usize>(&self, x: Tensor<B, D>) -> Tensor<B, D> {
+        match self {
+            Self::Relu => relu(x),
+            Self::Gelu => gelu(x),
+            Self::Silu => silu(x),
+        }
+    }
+}
+
+/// Pooling after the activation, with a window and stride of `size`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Pooling {
+    None,
+    Max { size: usize },
+    Avg { size: usize },
+}
+
+impl Pooling {
+    pub fn forward<B: Backend>(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        match *self {
+            Self::None => x,
+            Self::Max { size } => max_pool2d(x, [size, size], [size, size], [0, 0], [1, 1]),
+            Self::Avg { size } => avg_pool2d(x, [size, size], [size, size], [0, 0], true),
+        }
+    }
+}
+
+#[derive(Module, Debug)]
+pub enum Norm<B: Backend> {
+    Batch(BatchNorm<B>),
+    Group(GroupNorm<B>),
+}
+
+/// Fields are read by the int8 engine
+#[derive(Module, Debug)]
+pub struct ConvBlock<B: Backend> {
+    pub(crate) conv: Conv2d<B>,
+    pub(crate) norm: Option<Norm<B>>,
+    /// 1x1 projection of the input, when the residual shapes differ
+    pub(crate) projection: Option<Conv2d<B>>,
+    pub(crate) residual: bool,
+    pub(crate) activation: Ignored<Activation>,
+    pub(crate) pooling: Ignored<Pooling>,
+}
+
+impl<B: Backend> ConvBlock<B> {
+    pub fn new(device: &B::Device, in_channels: usize, config: &ConvBlockConfig) -> Self {
+        let kernel = config.kernel_size;
+        let stride = config.stride;
+        let conv = Conv2dConfig::new([in_channels, config.filters], [kernel, kernel])
+            .with_stride([stride, stride])
+            .with_padding(PaddingConfig2d::Explicit(kernel / 2, kernel / 2))
+            .init(device);
+        let norm = match config.norm {
+            Normalization::BatchNorm => Some(Norm::Batch(BatchNormConfig::new(config.filters).init(device))),
+            Normalization::GroupNorm { groups } => {
+                Some(Norm::Group(GroupNormConfig::new(groups, config.filters).init(device)))
+            }
+            Normalization::None => None,
+        };
+        let projection = (config.residual && (in_channels != config.filters || stride != 1)).then(|| {
+            Conv2dConfig::new([in_channels, config.filters], [1, 1])
+                .with_stride([stride, stride])
+                .init(device)
+        });
+
+        Self {
+            conv,
+            norm,
+            projection,
+            residual: config.residual,
+            activation: Ignored(config.activation),
+            pooling: Ignored(config.pooling),
+        }
+    }
+
+    pub fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        self.pool(self.activations(x))
+    }
+
+    /// Block output before pooling
+    pub fn activations(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        let mut y = self.conv.forward(x.clone());
+        y = match &self.norm {
+            Some(Norm::Batch(norm)) => norm.forward(y),
+            Some(Norm::Group(norm)) => norm.forward(y),
+            None => y,
+        };
+        if self.residual {
+            y = y + match &self.projection {
+                Some(projection) => projection.forward(x),
+                None => x,
+            };
+        }
+        self.activation.forward(y)
+    }
+
+    pub fn pool(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        self.pooling.forward(x)
+    }
+}
+
+/// Structured pruning
+impl<B: Backend> ConvBlock<B> {
+    /// L1 norm of each filter, `[filters]`
+    pub fn filter_norms(&self) -> Tensor<B, 1> {
+        let [filters, ..] = self.conv.weight.dims();
+        self.conv.weight.val().abs().reshape([filters as i32, -1]).sum_dim(1).reshape([filters])
+    }
+
+    /// Keeps the `kept` filters, their normalization channels and, when the previous block was
+    /// pruned, its `kept_inputs` channels
+    pub fn prune(mut self, kept_inputs: Option<&Tensor<B, 1, Int>>, kept: &Tensor<B, 1, Int>) -> Self {
+        self.conv = prune_conv(self.conv, kept_inputs, kept);
+        self.projection = self.projection.map(|projection| prune_conv(projection, kept_inputs, kept));
+        self.norm = self.norm.map(|norm| match norm {
+            Norm::Batch(mut norm) => {
+                norm.gamma = select(&norm.gamma, kept);
+                norm.beta = select(&norm.beta, kept);
+                norm.running_mean = RunningState::new(norm.running_mean.value().select(0, kept.clone()));
+                norm.running_var = RunningState::new(norm.running_var.value().select(0, kept.clone()));
+                Norm::Batch(norm)
+            }
+            Norm::Group(mut norm) => {
+                norm.gamma = norm.gamma.map(|gamma| select(&gamma, kept));
+                norm.beta = norm.beta.map(|beta| select(&beta, kept));
+                norm.num_channels = kept.dims()[0];
+                Norm::Group(norm)
+            }
+        });
+        self
+    }
+}
+
+fn prune_conv<B: Backend>(
+    mut conv: Conv2d<B>,
+    kept_inputs: Option<&Tensor<B, 1, Int>>,
+    kept: &Tensor<B, 1, Int>,
+) -> Conv2d<B> {
+    let mut weight = conv.weight.val().select(0, kept.clone());
+    if let Some(inputs) = kept_inputs {
+        weight = weight.select(1, inputs.clone());
+    }
+    conv.weight = Param::from_tensor(weight);
+    conv.bias = conv.bias.map(|bias| select(&bias, kept));
+    conv
+}
+
+fn select<B: Backend>(param: &Param<Tensor<B, 1>>, indices: &Tensor<B, 1, Int>) -> Param<Tensor<B, 1>> {
+    Param::from_tensor(param.val().select(0, indices.clone()))
+}
+
+/// Blocks applied in sequence
+#[derive(Module, Debug)]
+pub struct ConvStack<B: Backend> {
+    blocks: Vec<ConvBlock<B>>,
+}
+
+impl<B: Backend> ConvStack<B> {
+    pub fn new(device: &B::Device, in_channels: usize, configs: &[ConvBlockConfig]) -> Self {
+        // `ModelConfig::validate` rejects an empty custom stack before any model is built
+        assert!(!configs.is_empty(), "The architecture needs at least one conv block");
+        let mut channels = in_channels;
+        let blocks = configs
+            .iter()
+            .map(|config| {
+                let block = ConvBlock::new(device, channels, config);
+                channels = config.filters;
+                block
+            })
+            .collect();
+        Self { blocks }
+    }
+
+    pub fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        self.blocks.iter().fold(x, |x, block| block.forward(x))
+    }
+
+    /// Output of the last block before its pooling (used for class-activation maps)
+    pub fn last_activations(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        let (last, rest) = self.blocks.split_last().expect("at least one block");
+        last.activations(rest.iter().fold(x, |x, block| block.forward(x)))
+    }
+
+    /// Pooling of the last block, completing [`Self::last_activations`]
+    pub fn pool_last(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        self.blocks.last().expect("at least one block").pool(x)
+    }
+
+    pub fn blocks(&self) -> &[ConvBlock<B>] {
+        &self.blocks
+    }
+
+    /// Output of every block before its pooling (used to rank filters by activation)
+    pub fn block_activations(&self, x: Tensor<B, 4>) -> Vec<Tensor<B, 4>> {
+        let mut activations = Vec::with_capacity(self.blocks.len());
+        self.blocks.iter().fold(x, |x, block| {
+            let y = block.activations(x);
+            activations.push(y.clone());
+            block.pool(y)
+        });
+        activations
+    }
+
+    /// Keeps the filters listed for each block
+    pub fn prune(self, kept: &[Tensor<B, 1, Int>]) -> Self {
+        let mut kept_inputs = None;
+        let blocks = self
+            .blocks
+            .into_iter()
+            .zip(kept)
+            .map(|(block, kept)| {
+                let block = block.prune(kept_inputs, kept);
+                kept_inputs = Some(kept);
+                block
+            })
+            .collect();
+        Self { blocks }
+    }
+}
+
+impl<B: Backend> BackboneRecord<B> {
+    /// Puts the conv/BatchNorm pairs of a checkpoint saved before the conv stack was configurable
+    /// into the custom blocks, which must have the same shapes
+    pub fn load_legacy(&mut self, layers: Vec<(Conv2dRecord<B>, BatchNormRecord<B>)>) -> Result<()> {
+        let Self::Custom(stack) = self else {
+            bail!("Legacy checkpoints hold a custom conv stack");
+        };
+        ensure!(
+            stack.blocks.len() == layers.len(),
+            "Legacy checkpoints have {} conv blocks, the config {}",
+            layers.len(),
+            stack.blocks.len()
+        );
+        for (i, (block, (conv, norm))) in stack.blocks.iter_mut().zip(layers).enumerate() {
+            ensure!(
+                matches!(block.norm, Some(NormRecord::Batch(_))) && block.projection.is_none(),
+                "Conv block {} must use BatchNorm without projection to load a legacy checkpoint",
+                i + 1
+            );
+            ensure!(
+                block.conv.weight.dims() == conv.weight.dims(),
+                "Conv block {}: legacy weights {:?}, expected {:?}",
+                i + 1,
+                conv.weight.dims(),
+                block.conv.weight.dims()
+            );
+            block.conv = conv;
+            block.norm = Some(NormRecord::Batch(norm));
+        }
+        Ok(())
+    }
+}
+
+/// Feature extractor selected by [`Architecture`]
+#[derive(Module, Debug)]
+#[allow(clippy::large_enum_variant)] // Built once per model
+pub enum Backbone<B: Backend> {
+    Custom(ConvStack<B>),
+    ResNet18(ResNet18<B>),
+    MobileNetV2(MobileNetV2<B>),
+}
+
+impl<B: Backend> Backbone<B> {
+    pub fn new(
+        device: &B::Device,
+        in_channels: usize,
+        architecture: &Architecture,
+        conv_blocks: &[ConvBlockConfig],
+    ) -> Self {
+        match *architecture {
+            Architecture::Custom => Self::Custom(ConvStack::new(device, in_channels, conv_blocks)),
+            Architecture::ResNet18 { base_width } => Self::ResNet18(ResNet18::new(device, in_channels, base_width)),
+            Architecture::MobileNetV2 { width_multiplier } => {
+                Self::MobileNetV2(MobileNetV2::new(device, in_channels, width_multiplier))
+            }
+        }
+    }
+
+    pub fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        match self {
+            Self::Custom(stack) => stack.forward(x),
+            Self::ResNet18(net) => net.forward(x),
+            Self::MobileNetV2(net) => net.forward(x),
+        }
+    }
+
+    /// Last feature map before any final pooling (used for class-activation maps)
+    pub fn last_activations(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        match self {
+            Self::Custom(stack) => stack.last_activations(x),
+            Self::ResNet18(net) => net.forward(x),
+            Self::MobileNetV2(net) => net.forward(x),
+        }
+    }
+
+    /// Completes [`Self::last_activations`]
+    pub fn pool_last(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        match self {
+            Self::Custom(stack) => stack.pool_last(x),
+            Self::ResNet18(_) | Self::MobileNetV2(_) => x,
+        }
+    }
+}
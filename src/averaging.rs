@@ -0,0 +1,160 @@
+//! Weight averaging: exponential moving average during training, or SWA over the last checkpoints
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::anyhow;
+use burn::{
+    data::dataloader::DataLoader,
+    module::{Module, ModuleMapper, ModuleVisitor, Param},
+    optim::{GradientsParams, Optimizer},
+    record::{BinFileRecorder, FullPrecisionSettings, Recorder},
+    tensor::{
+        backend::{AutodiffBackend, Backend},
+        Tensor,
+    },
+};
+use crate::{
+    data::MalariaBatch,
+    malaria_cnn::{MalariaCNN, MalariaCNNRecord},
+};
+
+/// Batches used to re-estimate BatchNorm statistics; with Burn's momentum of 0.1 the
+/// statistics the averaged weights started with then weigh 0.9^100, less than 0.01%
+const BATCH_NORM_BATCHES: usize = 100;
+
+/// Every float parameter, flattened, in visiting order
+struct FlatParams<B: Backend>(Vec<Tensor<B, 1>>);
+
+impl<B: Backend> ModuleVisitor<B> for FlatParams<B> {
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        self.0.push(untracked(param.val()).reshape([-1]));
+    }
+}
+
+/// Autodiff's `detach` keeps the tensor a leaf requiring gradients; the average must not be tracked
+fn untracked<B: Backend, const D: usize>(tensor: Tensor<B, D>) -> Tensor<B, D> {
+    tensor.detach().set_require_grad(false)
+}
+
+/// Moves each parameter towards the matching one of another model
+struct Blend<B: Backend> {
+    params: std::vec::IntoIter<Tensor<B, 1>>,
+    weight: f64,
+}
+
+impl<B: Backend> ModuleMapper<B> for Blend<B> {
+    fn map_float<const D: usize>(&mut self, param: Param<Tensor<B, D>>) -> Param<Tensor<B, D>> {
+        let (id, tensor, mapper) = param.consume();
+        let other = self.params.next().expect("models with the same architecture").reshape(tensor.dims());
+        let tensor = untracked(tensor).mul_scalar(1.0 - self.weight) + other.mul_scalar(self.weight);
+        Param::from_mapped_value(id, tensor, mapper)
+    }
+}
+
+/// `(1 - weight) · average + weight · model`, parameter by parameter. Burn visits the BatchNorm
+/// running statistics like parameters, so they are blended too; [`recompute_batch_norm`] replaces
+/// them before the averaged weights are exported.
+pub fn blend<B: Backend, M: Module<B>>(average: M, model: &M, weight: f64) -> M {
+    let mut params = FlatParams(Vec::new());
+    model.visit(&mut params);
+    average.map(&mut Blend { params: params.0.into_iter(), weight })
+}
+
+/// Mean of the model checkpoints of `epochs` in `checkpoint_dir`, loaded into copies of `model`
+pub fn average_checkpoints<B: Backend>(
+    model: &MalariaCNN<B>,
+    checkpoint_dir: &Path,
+    epochs: &[usize],
+    device: &B::Device,
+) -> anyhow::Result<MalariaCNN<B>> {
+    let mut average: Option<MalariaCNN<B>> = None;
+    for (k, epoch) in epochs.iter().enumerate() {
+        let record = BinFileRecorder::<FullPrecisionSettings>::new()
+            .load(checkpoint_dir.join(format!("model-{}", epoch)), device)
+            .map_err(|e| anyhow!("Lecture du checkpoint de l'époque {}: {}", epoch, e))?;
+        let checkpoint = model.clone().load_record(record);
+        average = Some(match average {
+            None => checkpoint,
+            Some(average) => blend(average, &checkpoint, 1.0 / (k + 1) as f64),
+        });
+    }
+    average.ok_or_else(|| anyhow!("Aucun checkpoint à moyenner dans {}", checkpoint_dir.display()))
+}
+
+/// Re-estimates the BatchNorm running statistics of averaged weights with training-mode passes
+pub fn recompute_batch_norm<B: AutodiffBackend>(
+    model: &MalariaCNN<B>,
+    dataloader: &dyn DataLoader<B, MalariaBatch<B>>,
+) {
+    let mut seen = 0;
+    while seen < BATCH_NORM_BATCHES {
+        let before = seen;
+        for batch in dataloader.iter().take(BATCH_NORM_BATCHES - seen) {
+            let _ = model.forward(batch.images);
+            seen += 1;
+        }
+        if seen == before {
+            break;
+        }
+    }
+}
+
+/// Keeps an exponential moving average of the model parameters, updated after every optimizer step.
+///
+/// The average lives on the autodiff backend without tracking gradients, so that it is saved with
+/// the optimizer state and survives a resume.
+#[derive(Clone)]
+pub struct EmaOptimizer<O, B: AutodiffBackend> {
+    inner: O,
+    decay: f64,
+    ema: Option<Arc<Mutex<MalariaCNN<B>>>>,
+}
+
+impl<O, B: AutodiffBackend> EmaOptimizer<O, B> {
+    /// Averages from `model` on when `decay` is set
+    pub fn new(inner: O, model: &MalariaCNN<B>, decay: Option<f64>) -> Self {
+        Self {
+            inner,
+            decay: decay.unwrap_or(0.0),
+            ema: decay.map(|_| Arc::new(Mutex::new(model.clone()))),
+        }
+    }
+
+    /// Shared handle on the average, read once training is over
+    pub fn average(&self) -> Option<Arc<Mutex<MalariaCNN<B>>>> {
+        self.ema.clone()
+    }
+}
+
+impl<B, O> Optimizer<MalariaCNN<B>, B> for EmaOptimizer<O, B>
+where
+    B: AutodiffBackend,
+    O: Optimizer<MalariaCNN<B>, B>,
+{
+    type Record = (O::Record, Option<MalariaCNNRecord<B>>);
+
+    fn step(&mut self, lr: f64, module: MalariaCNN<B>, grads: GradientsParams) -> MalariaCNN<B> {
+        let module = self.inner.step(lr, module, grads);
+        if let Some(ema) = &self.ema {
+            let mut ema = ema.lock().unwrap();
+            *ema = blend(ema.clone(), &module, 1.0 - self.decay);
+        }
+        module
+    }
+
+    fn to_record(&self) -> Self::Record {
+        let ema = self.ema.as_ref().map(|ema| ema.lock().unwrap().clone().into_record());
+        (self.inner.to_record(), ema)
+    }
+
+    fn load_record(self, (record, ema_record): Self::Record) -> Self {
+        if let (Some(ema), Some(ema_record)) = (&self.ema, ema_record) {
+            let mut ema = ema.lock().unwrap();
+            *ema = ema.clone().load_record(ema_record);
+        }
+        Self { inner: self.inner.load_record(record), ..self }
+    }
+}
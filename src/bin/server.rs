@@ -1,51 +1,111 @@
-use std::{net::SocketAddr, path::PathBuf, time::Instant};
+use std::{io::Cursor, net::SocketAddr, path::PathBuf, sync::{Arc, Mutex}, time::Instant};
 use std::env;
-use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use axum::{
-    extract::{Multipart, State},
-    http::{HeaderValue, Method, StatusCode},
-    response::IntoResponse,
+    extract::{Multipart, Query, State},
+    http::{header, Method, StatusCode},
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
-use image::{imageops::FilterType, ImageReader};
-use serde::Serialize;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
 use tokio::net::TcpListener;
 use tower_http::cors::{Any, CorsLayer};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-// Burn (CPU backend) for native inference
-use burn::{
-    prelude::Module,
-    record::{BinFileRecorder, FullPrecisionSettings, Recorder},
-    tensor::Tensor,
-};
+use burn::{backend::Autodiff, module::Module, tensor::Tensor};
 use burn_ndarray::{NdArray, NdArrayDevice};
+use malaria_classifier::{
+    classifier::{decode_image, load_explain_model},
+    ensemble::EnsembleMethod,
+    malaria_cnn::{CamMethod, MalariaCNN},
+    tta::TtaMode,
+    CellClass, MalariaClassifier, MalariaEnsemble, ModelConfig, Preprocessor,
+};
 
-// Local, inference-only model definition (no training deps)
-#[path = "./malaria_cnn_infer.rs"]
-mod malaria_cnn;
-use malaria_cnn::MalariaCNN;
+/// Upper bound on `mc_samples` so a single request cannot monopolize the CPU
+const MAX_MC_SAMPLES: usize = 256;
 
+/// Burn parameters are not `Sync`, so handlers lock the models just long enough to clone them
+/// (clones share the weights loaded at startup)
 #[derive(Clone)]
-struct AppConfig {
-    image_height: usize,
-    image_width: usize,
-    num_classes: usize,
+struct BurnState {
+    /// Served models: one model, or the members of an ensemble
+    members: Arc<Mutex<Vec<MalariaClassifier<NdArray>>>>,
+    /// First member on the autodiff backend, for Grad-CAM
+    explainer: Arc<Mutex<MalariaCNN<Autodiff<NdArray>>>>,
+    /// How member predictions are combined when there are several
+    method: EnsembleMethod,
 }
 
-#[derive(Clone)]
-struct BurnState {
-    cfg: AppConfig,
-    model_path: PathBuf,
+#[derive(Deserialize)]
+struct PredictParams {
+    /// Number of Monte Carlo dropout passes; plain eval-mode inference when absent
+    mc_samples: Option<usize>,
+    /// Test-time augmentation views to average (`flips` or `dihedral`)
+    tta: Option<TtaMode>,
 }
 
 #[derive(Serialize)]
 struct PredictResponse {
     class: String,
     probabilities: [f32; 2],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uncertainty: Option<UncertaintyResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ensemble: Option<EnsembleResponse>,
+}
+
+#[derive(Serialize)]
+struct UncertaintyResponse {
+    mc_samples: usize,
+    variance: [f32; 2],
+    mutual_information: f32,
+}
+
+/// Spread of the ensemble members on the image
+#[derive(Serialize)]
+struct EnsembleResponse {
+    members: usize,
+    method: EnsembleMethod,
+    /// Fraction of the members not voting for the predicted class
+    disagreement: f32,
+    variance: [f32; 2],
+    mutual_information: f32,
+}
+
+#[derive(Deserialize)]
+struct ExplainParams {
+    /// `gradcam` (default) or `gradcampp`
+    method: Option<CamMethod>,
+    /// Class index to explain; defaults to the predicted class
+    class: Option<usize>,
+    /// Heatmap opacity in the overlay, between 0 and 1 (default 0.5)
+    alpha: Option<f32>,
+    /// `json` (default) or `png` to receive the overlay image directly
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ExplainResponse {
+    class: String,
+    probabilities: [f32; 2],
+    method: CamMethod,
+    heatmap: HeatmapResponse,
+    /// Base64-encoded PNG of the heatmap blended over the uploaded image
+    overlay_png: String,
+}
+
+#[derive(Serialize)]
+struct HeatmapResponse {
+    width: usize,
+    height: usize,
+    /// Row-major values in [0, 1] spread over the uploaded image, null where the model input
+    /// cropped it away
+    values: Vec<Vec<Option<f32>>>,
 }
 
 #[tokio::main]
@@ -59,32 +119,40 @@ async fn main() -> Result<()> {
         .compact()
         .init();
 
-    // Inference config must match training
-    let cfg = AppConfig {
-        image_height: 128,
-        image_width: 128,
-        num_classes: 2,
+    // MODEL_PATHS (comma-separated) serves an ensemble; otherwise MODEL_PATH, defaulting to the Burn checkpoint
+    let members = match env::var("MODEL_PATHS") {
+        Ok(paths) => paths
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(|path| load_member(PathBuf::from(path), None))
+            .collect::<Result<Vec<_>>>()?,
+        Err(_) => {
+            let model_path = env::var("MODEL_PATH").unwrap_or_else(|_| "./malaria-model.bin".to_string());
+            vec![load_member(PathBuf::from(model_path), env::var("MODEL_CONFIG").ok().map(PathBuf::from))?]
+        }
     };
-
-    // Allow overriding model path via env var MODEL_PATH; default to Burn checkpoint
-    let model_path_str = env::var("MODEL_PATH").unwrap_or_else(|_| "./malaria-model.bin".to_string());
-    let model_path = PathBuf::from(&model_path_str);
-
-    // Proactive existence check to provide a clearer error message
-    if !model_path.exists() {
-        let cwd = std::env::current_dir().ok();
-        let hint = "Expected a Burn checkpoint (.bin). Ensure the file exists or set MODEL_PATH to the checkpoint path.";
-        let cwd_msg = cwd.map(|p| format!(" Current dir: {}.", p.display())).unwrap_or_default();
-        anyhow::bail!(
-            "Model checkpoint not found at {}. {}{}",
-            model_path.display(),
-            hint,
-            cwd_msg
-        );
+    let Some((explain_path, _)) = members.first() else {
+        anyhow::bail!("MODEL_PATHS lists no checkpoint");
+    };
+    // Grad-CAM maps are specific to one architecture: an ensemble is explained by its first member.
+    // Only the last conv activations are differentiated, so the weights need no gradient tracking
+    let explainer = load_explain_model(explain_path, members[0].1.config(), &NdArrayDevice::default())?.no_grad();
+    let members: Vec<_> = members.into_iter().map(|(_, classifier)| classifier).collect();
+    let num_members = members.len();
+    let method = match env::var("ENSEMBLE_METHOD") {
+        Ok(method) => method.parse()?,
+        Err(_) => EnsembleMethod::Mean,
+    };
+    if num_members > 1 {
+        info!(members = num_members, ?method, "Serving an ensemble");
     }
-    info!(path = %model_path.display(), "Loading Burn checkpoint path configured");
-    // Store only config and path in state to keep it Send + Sync
-    let state = BurnState { cfg, model_path };
+
+    let state = BurnState {
+        members: Arc::new(Mutex::new(members)),
+        explainer: Arc::new(Mutex::new(explainer)),
+        method,
+    };
 
     // CORS: allow dev UIs (Vite default 5173, others) — relax to Any for development simplicity
     let cors = CorsLayer::new()
@@ -95,6 +163,7 @@ async fn main() -> Result<()> {
     let router = Router::new()
         .route("/health", get(health))
         .route("/predict", post(predict))
+        .route("/explain", post(explain))
         .with_state(state)
         .layer(cors);
 
@@ -110,117 +179,268 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Loads a checkpoint with the configuration saved next to it (or at `config_path`, from MODEL_CONFIG)
+fn load_member(model_path: PathBuf, config_path: Option<PathBuf>) -> Result<(PathBuf, MalariaClassifier<NdArray>)> {
+    // Proactive existence check to provide a clearer error message
+    if !model_path.exists() {
+        let cwd = std::env::current_dir().ok();
+        let hint = "Expected a Burn checkpoint (.bin). Ensure the file exists or set MODEL_PATH to the checkpoint path.";
+        let cwd_msg = cwd.map(|p| format!(" Current dir: {}.", p.display())).unwrap_or_default();
+        anyhow::bail!(
+            "Model checkpoint not found at {}. {}{}",
+            model_path.display(),
+            hint,
+            cwd_msg
+        );
+    }
+    info!(path = %model_path.display(), "Loading Burn checkpoint path configured");
+
+    // Training saves its ModelConfig next to the checkpoint
+    let config_path = config_path.unwrap_or_else(|| model_path.with_extension("json"));
+    let cfg = if config_path.exists() {
+        ModelConfig::load(&config_path)?
+    } else {
+        warn!(path = %config_path.display(), "Model config not found, assuming the default architecture");
+        ModelConfig::default()
+    };
+    info!(architecture = ?cfg.architecture, channels = cfg.image_channels, "Model architecture");
+    if cfg.quantization.is_some() {
+        info!("Int8 model, served by the int8 engine");
+    }
+    let preprocessor = cfg.preprocessor()?;
+    info!(
+        resize = ?preprocessor.resize,
+        filter = ?preprocessor.filter,
+        channel_mode = ?preprocessor.channel_mode,
+        normalization = ?preprocessor.normalization,
+        "Preprocessing"
+    );
+    let classifier = MalariaClassifier::<NdArray>::from_checkpoint(&model_path, cfg, &NdArrayDevice::default())?;
+    info!("Checkpoint loaded");
+    Ok((model_path, classifier))
+}
+
 async fn health() -> impl IntoResponse {
     info!("/health called");
     "ok"
 }
 
-async fn predict(State(state): State<BurnState>, mut multipart: Multipart) -> impl IntoResponse {
+async fn predict(
+    State(state): State<BurnState>,
+    Query(params): Query<PredictParams>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
     let t_total = Instant::now();
     let req_id = uuid::Uuid::new_v4();
-    info!(%req_id, "Predict request started");
-    // Pull first part named 'image'
-    let mut image_bytes: Option<Vec<u8>> = None;
-    while let Ok(Some(field)) = multipart.next_field().await {
-        if field.name() == Some("image") {
-            match field.bytes().await {
-                Ok(b) => { 
-                    debug!(%req_id, size = b.len(), "Received image bytes");
-                    image_bytes = Some(b.to_vec()); break; 
-                }
-                Err(e) => {
-                    error!(%req_id, error = %e, "Reading multipart field failed");
-                    return (StatusCode::BAD_REQUEST, format!("Invalid image upload: {}", e)).into_response()
-                },
-            }
+    info!(%req_id, mc_samples = ?params.mc_samples, tta = ?params.tta, "Predict request started");
+    if let Some(n) = params.mc_samples {
+        if n == 0 || n > MAX_MC_SAMPLES {
+            return (StatusCode::BAD_REQUEST, format!("mc_samples must be between 1 and {}", MAX_MC_SAMPLES)).into_response();
         }
     }
+    let image_bytes = match read_image_field(&mut multipart, req_id).await {
+        Ok(b) => b,
+        Err(resp) => return resp,
+    };
 
-    let image_bytes = match image_bytes { Some(b) => b, None => return (StatusCode::BAD_REQUEST, "No 'image' field provided").into_response() };
-
-    // Decode and preprocess to CHW f32 [0,1]
+    // Decode once; each member resizes to its own input size
     let t_pre = Instant::now();
-    let chw = match preprocess_bytes(&image_bytes, state.cfg.image_height, state.cfg.image_width) {
-        Ok(v) => v,
+    let image = match decode_image(&image_bytes) {
+        Ok(img) => img,
         Err(e) => {
             error!(%req_id, error = %e, "Preprocess failed");
             return (StatusCode::BAD_REQUEST, format!("Preprocess failed: {}", e)).into_response()
         },
     };
-    debug!(%req_id, ms = t_pre.elapsed().as_millis() as u64, "Preprocessing done");
+    debug!(%req_id, ms = t_pre.elapsed().as_millis() as u64, "Decoding done");
 
-    // Prepare device and model per request (simple and thread-safe)
-    let device = NdArrayDevice::default();
+    // Clones share the weights loaded at startup and only carry this request's options
+    let classifiers: Result<Vec<_>> = lock(&state.members)
+        .iter()
+        .map(|classifier| {
+            let classifier = match params.tta {
+                Some(mode) => classifier.clone().with_tta(mode),
+                None => classifier.clone(),
+            };
+            match params.mc_samples {
+                Some(n) => classifier.with_mc_samples(n),
+                None => Ok(classifier),
+            }
+        })
+        .collect();
+    let mut classifiers = match classifiers {
+        Ok(classifiers) => classifiers,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
 
-    // Build Burn tensor [1, 3, H, W]
-    let input_1d: Tensor<NdArray, 1> = Tensor::<NdArray, 1>::from_floats(chw.as_slice(), &device);
-    let input: Tensor<NdArray, 4> = input_1d.reshape([1, 3, state.cfg.image_height, state.cfg.image_width]);
+    let t_inf = Instant::now();
+    let result = match classifiers.len() {
+        1 => classifiers.remove(0).predict_image(&image).map(|prediction| (prediction, None)),
+        _ => MalariaEnsemble::new(classifiers, state.method).and_then(|ensemble| ensemble.predict_image(&image)).map(|p| {
+            let spread = EnsembleResponse {
+                members: p.members,
+                method: p.method,
+                disagreement: p.disagreement,
+                variance: p.variance,
+                mutual_information: p.mutual_information,
+            };
+            (p.prediction, Some(spread))
+        }),
+    };
+    let (prediction, ensemble) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            error!(%req_id, error = ?e, "Inference failed");
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Inference failed: {:?}", e)).into_response();
+        }
+    };
+    debug!(%req_id, views = ?params.tta, ms = t_inf.elapsed().as_millis() as u64, "Inference done");
 
-    // Instantiate and load model weights
-    let t_load = Instant::now();
-    let mut model: MalariaCNN<NdArray> = MalariaCNN::new(
-        &device,
-        3, 16, 32, 64, 128, 64, 2, 0.3,
-    );
-    let record = match BinFileRecorder::<FullPrecisionSettings>::new().load(state.model_path.clone().into(), &device) {
-        Ok(r) => r,
+    let probs = prediction.probabilities;
+    let class = prediction.class.name();
+    let uncertainty = prediction.uncertainty.map(|u| UncertaintyResponse {
+        mc_samples: u.mc_samples,
+        variance: u.variance,
+        mutual_information: u.mutual_information,
+    });
+    info!(%req_id, class, p0 = probs[0], p1 = probs[1], total_ms = t_total.elapsed().as_millis() as u64, "Prediction ready");
+    Json(PredictResponse { class: class.to_string(), probabilities: probs, uncertainty, ensemble }).into_response()
+}
+
+async fn explain(
+    State(state): State<BurnState>,
+    Query(params): Query<ExplainParams>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    let t_total = Instant::now();
+    let req_id = uuid::Uuid::new_v4();
+    let method = params.method.unwrap_or(CamMethod::GradCam);
+    let alpha = params.alpha.unwrap_or(0.5);
+    info!(%req_id, ?method, class = ?params.class, "Explain request started");
+    // Grad-CAM maps are specific to one architecture: an ensemble is explained by its first member
+    let member = lock(&state.members)[0].clone();
+    let num_classes = member.config().num_classes;
+    if !(0.0..=1.0).contains(&alpha) {
+        return (StatusCode::BAD_REQUEST, "alpha must be between 0 and 1").into_response();
+    }
+    if params.class.is_some_and(|c| c >= num_classes) {
+        return (StatusCode::BAD_REQUEST, format!("class must be below {}", num_classes)).into_response();
+    }
+    let as_png = match params.format.as_deref() {
+        None | Some("json") => false,
+        Some("png") => true,
+        Some(other) => return (StatusCode::BAD_REQUEST, format!("Unknown format '{}', expected json or png", other)).into_response(),
+    };
+
+    let image_bytes = match read_image_field(&mut multipart, req_id).await {
+        Ok(b) => b,
+        Err(resp) => return resp,
+    };
+    let image = match decode_image(&image_bytes) {
+        Ok(img) => img,
         Err(e) => {
-            error!(%req_id, error = ?e, path = %state.model_path.display(), "Failed to load checkpoint");
-            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load checkpoint: {:?}", e)).into_response()
+            error!(%req_id, error = %e, "Decode failed");
+            return (StatusCode::BAD_REQUEST, format!("Preprocess failed: {}", e)).into_response()
         },
     };
-    model = model.load_record(record);
-    debug!(%req_id, ms = t_load.elapsed().as_millis() as u64, "Model loaded");
+    let preprocessor = member.preprocessor();
+    let chw = preprocessor.process(&image);
 
-    let t_inf = Instant::now();
-    let logits: Tensor<NdArray, 2> = model.forward(input);
-    let logits_data = logits.into_data();
-    let logits_vec: Vec<f32> = match logits_data.to_vec::<f32>() {
-        Ok(v) => v,
+    let device = NdArrayDevice::default();
+    let input_1d: Tensor<NdArray, 1> = Tensor::<NdArray, 1>::from_floats(chw.as_slice(), &device);
+    let Preprocessor { channels, height, width, .. } = *preprocessor;
+    let input: Tensor<NdArray, 4> = input_1d.reshape([1, channels, height, width]);
+    let t_cam = Instant::now();
+    let model = lock(&state.explainer).clone();
+    let cam = model.grad_cam(input, params.class, method);
+    let [map_height, map_width] = cam.heatmap.dims();
+    let (probs, heatmap) = match (cam.probabilities.into_data().to_vec::<f32>(), cam.heatmap.into_data().to_vec::<f32>()) {
+        (Ok(p), Ok(h)) if p.len() >= 2 => (p, h),
+        _ => {
+            error!(%req_id, "Failed to read Grad-CAM output");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read Grad-CAM output").into_response();
+        }
+    };
+    debug!(%req_id, ms = t_cam.elapsed().as_millis() as u64, map_height, map_width, "Grad-CAM done");
+
+    // The heatmap covers the model input: the padding and crop are undone so that it lines up with the upload
+    let (image_width, image_height) = (image.width(), image.height());
+    let on_image = preprocessor.map_to_image(&heatmap, map_width, (image_width, image_height), image_width as usize, image_height as usize);
+    let overlay = match render_overlay(&image, &on_image, alpha) {
+        Ok(png) => png,
         Err(e) => {
-            error!(%req_id, error = ?e, "Failed to read logits");
-            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read logits: {:?}", e)).into_response()
+            error!(%req_id, error = %e, "Overlay rendering failed");
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Overlay rendering failed: {}", e)).into_response()
         },
     };
-    debug!(%req_id, ms = t_inf.elapsed().as_millis() as u64, "Inference done");
+    let class = CellClass::ALL.get(cam.class_idx).map_or("Unknown", |class| class.name());
+    info!(%req_id, class, total_ms = t_total.elapsed().as_millis() as u64, "Explanation ready");
 
-    // Softmax
-    let probs_vec = softmax(&logits_vec);
-    if probs_vec.len() < 2 {
-        error!(%req_id, len = probs_vec.len(), "Invalid model output length");
-        return (StatusCode::INTERNAL_SERVER_ERROR, "Invalid model output length").into_response();
+    if as_png {
+        return ([(header::CONTENT_TYPE, "image/png")], overlay).into_response();
     }
-    let probs = [probs_vec[0], probs_vec[1]];
-    let class_idx = if probs[1] >= probs[0] { 1 } else { 0 };
-    let class = if class_idx == 1 { "Parasitized" } else { "Uninfected" };
-    info!(%req_id, class, p0 = probs[0], p1 = probs[1], total_ms = t_total.elapsed().as_millis() as u64, "Prediction ready");
-    Json(PredictResponse { class: class.to_string(), probabilities: probs }).into_response()
-}
-
-fn preprocess_bytes(bytes: &[u8], target_height: usize, target_width: usize) -> Result<Vec<f32>> {
-    let img = ImageReader::new(std::io::Cursor::new(bytes))
-        .with_guessed_format()
-        .context("Unsupported image format")?
-        .decode()
-        .context("Failed to decode image")?
-        .resize_exact(target_width as u32, target_height as u32, FilterType::Triangle)
-        .to_rgb8();
-
-    let raw = img.into_raw();
-    let frame = target_height * target_width;
-    let mut chw = vec![0.0f32; frame * 3];
-    for (i, pix) in raw.chunks_exact(3).enumerate() {
-        chw[i] = pix[0] as f32 / 255.0;
-        chw[i + frame] = pix[1] as f32 / 255.0;
-        chw[i + frame * 2] = pix[2] as f32 / 255.0;
+    Json(ExplainResponse {
+        class: class.to_string(),
+        probabilities: [probs[0], probs[1]],
+        method,
+        heatmap: HeatmapResponse {
+            width: map_width,
+            height: map_height,
+            values: preprocessor
+                .map_to_image(&heatmap, map_width, (image_width, image_height), map_width, map_height)
+                .chunks(map_width)
+                .map(|row| row.to_vec())
+                .collect(),
+        },
+        overlay_png: BASE64.encode(&overlay),
+    })
+    .into_response()
+}
+
+/// A panicking handler cannot leave the models half-updated: they are only ever cloned
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Pulls the first multipart part named `image`
+async fn read_image_field(multipart: &mut Multipart, req_id: uuid::Uuid) -> Result<Vec<u8>, Response> {
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some("image") {
+            return match field.bytes().await {
+                Ok(b) => {
+                    debug!(%req_id, size = b.len(), "Received image bytes");
+                    Ok(b.to_vec())
+                }
+                Err(e) => {
+                    error!(%req_id, error = %e, "Reading multipart field failed");
+                    Err((StatusCode::BAD_REQUEST, format!("Invalid image upload: {}", e)).into_response())
+                }
+            };
+        }
     }
-    Ok(chw)
+    Err((StatusCode::BAD_REQUEST, "No 'image' field provided").into_response())
+}
+
+/// Blends the per-pixel heat with a jet colormap, leaving the pixels the model did not see untouched
+fn render_overlay(image: &DynamicImage, heat: &[Option<f32>], alpha: f32) -> Result<Vec<u8>> {
+    let mut rgb = image.to_rgb8();
+    for (pixel, heat) in rgb.pixels_mut().zip(heat) {
+        let Some(heat) = heat else { continue };
+        let color = jet(heat.clamp(0.0, 1.0));
+        for c in 0..3 {
+            pixel[c] = ((1.0 - alpha) * pixel[c] as f32 + alpha * color[c]).round() as u8;
+        }
+    }
+
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(rgb)
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .context("Failed to encode PNG")?;
+    Ok(png)
 }
 
-fn softmax(v: &[f32]) -> Vec<f32> {
-    if v.is_empty() { return vec![]; }
-    let max = v.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
-    let exps: Vec<f32> = v.iter().map(|x| (*x - max).exp()).collect();
-    let sum: f32 = exps.iter().sum();
-    exps.into_iter().map(|e| e / sum).collect()
+/// Jet colormap: blue (0) -> cyan -> yellow -> red (1), as 0-255 RGB
+fn jet(v: f32) -> [f32; 3] {
+    let channel = |offset: f32| (1.5 - (4.0 * v - offset).abs()).clamp(0.0, 1.0) * 255.0;
+    [channel(3.0), channel(2.0), channel(1.0)]
 }
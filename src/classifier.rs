@@ -0,0 +1,408 @@
+//! Embeddable inference API: loads a model bundle and classifies cell images
+
+use std::{io::Cursor, path::Path, sync::Arc};
+
+use anyhow::{anyhow, bail, Context, Result};
+use burn::{
+    module::{Module, ModuleMapper, Param},
+    record::{BinBytesRecorder, BinFileRecorder, FullPrecisionSettings, Recorder},
+    tensor::{
+        activation::softmax,
+        backend::{AutodiffBackend, Backend},
+        Tensor,
+    },
+};
+use image::{DynamicImage, ImageReader};
+use serde::Serialize;
+
+use crate::{
+    architecture::{Architecture, ConvBlockConfig},
+    config::ModelConfig,
+    ensemble::{combine, EnsembleMethod},
+    int8::Int8Model,
+    malaria_cnn::{LegacyMalariaCNNRecord, MalariaCNN, MalariaCNNRecord},
+    preprocessing::Preprocessor,
+    tta::{average_views, TtaMode},
+};
+
+/// Class predicted for a cell image, in model output order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CellClass {
+    Uninfected,
+    Parasitized,
+}
+
+impl CellClass {
+    pub const ALL: [Self; 2] = [Self::Uninfected, Self::Parasitized];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Uninfected => "Uninfected",
+            Self::Parasitized => "Parasitized",
+        }
+    }
+
+    /// Most probable class; a tie goes to `Parasitized`
+    fn from_probabilities(probabilities: [f32; 2]) -> Self {
+        if probabilities[1] >= probabilities[0] { Self::Parasitized } else { Self::Uninfected }
+    }
+}
+
+/// Prediction for one image
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Prediction {
+    pub class: CellClass,
+    /// Probability of each class, indexed like [`CellClass::ALL`]
+    pub probabilities: [f32; 2],
+    /// Spread of the Monte Carlo dropout passes, when enabled
+    pub uncertainty: Option<McUncertainty>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct McUncertainty {
+    pub mc_samples: usize,
+    pub variance: [f32; 2],
+    pub mutual_information: f32,
+}
+
+/// Prediction of an ensemble for one image, with the spread of its members
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EnsemblePrediction {
+    /// Combined prediction; MC dropout statistics are averaged over the members
+    pub prediction: Prediction,
+    pub members: usize,
+    pub method: EnsembleMethod,
+    /// Fraction of the members not voting for the majority class
+    pub disagreement: f32,
+    pub variance: [f32; 2],
+    pub mutual_information: f32,
+}
+
+/// Model output for a batch of images, before it is read back
+struct BatchOutput<B: Backend> {
+    /// `[batch, classes]`
+    probabilities: Tensor<B, 2>,
+    /// MC dropout samples, variance `[batch, classes]` and mutual information `[batch]`
+    mc: Option<(usize, Tensor<B, 2>, Tensor<B, 1>)>,
+}
+
+/// A trained model with its preprocessing, ready to classify images.
+///
+/// ```no_run
+/// use burn_ndarray::{NdArray, NdArrayDevice};
+/// use malaria_classifier::MalariaClassifier;
+///
+/// let classifier = MalariaClassifier::<NdArray>::from_bundle("./malaria-model.bin", &NdArrayDevice::Cpu)?;
+/// let prediction = classifier.predict_path("cell.png")?;
+/// println!("{} {:?}", prediction.class.name(), prediction.probabilities);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+/// Clones share the weights, so a loaded classifier can be cloned cheaply to change its options.
+#[derive(Clone)]
+pub struct MalariaClassifier<B: Backend> {
+    config: ModelConfig,
+    preprocessor: Preprocessor,
+    model: InferenceModel<B>,
+    device: B::Device,
+    tta: Option<TtaMode>,
+    mc_samples: Option<usize>,
+}
+
+impl<B: Backend> MalariaClassifier<B> {
+    /// Loads the weights at `path` (`.bin`, the extension is optional) with the configuration
+    /// training saved next to them (`.json`)
+    pub fn from_bundle(path: impl AsRef<Path>, device: &B::Device) -> Result<Self> {
+        let path = path.as_ref().with_extension("");
+        let config = ModelConfig::load(&path.with_extension("json"))?;
+        Self::from_checkpoint(path, config, device)
+    }
+
+    /// Loads the weights at `path` with an explicit configuration
+    pub fn from_checkpoint(path: impl AsRef<Path>, config: ModelConfig, device: &B::Device) -> Result<Self> {
+        if config.num_classes != CellClass::ALL.len() {
+            bail!("Expected a {}-class model, got {} classes", CellClass::ALL.len(), config.num_classes);
+        }
+        let preprocessor = config.preprocessor()?;
+        let model = load_inference_model(path.as_ref(), &config, device)?;
+        Ok(Self { config, preprocessor, model, device: device.clone(), tta: None, mc_samples: None })
+    }
+
+    /// Averages the predictions over flipped/rotated views of each image
+    pub fn with_tta(mut self, tta: TtaMode) -> Self {
+        self.tta = Some(tta);
+        self
+    }
+
+    /// Runs `samples` (at least one) Monte Carlo dropout passes and reports their spread.
+    /// Int8 models have no dropout to sample.
+    pub fn with_mc_samples(mut self, samples: usize) -> Result<Self> {
+        if samples == 0 {
+            bail!("MC dropout needs at least one sample");
+        }
+        if let InferenceModel::Int8(_) = self.model {
+            bail!("MC dropout is not available on int8 models");
+        }
+        self.mc_samples = Some(samples);
+        Ok(self)
+    }
+
+    /// Configuration the model was trained with
+    pub fn config(&self) -> &ModelConfig {
+        &self.config
+    }
+
+    /// Preprocessing applied to every image before the forward pass
+    pub fn preprocessor(&self) -> &Preprocessor {
+        &self.preprocessor
+    }
+
+    pub fn predict_path(&self, path: impl AsRef<Path>) -> Result<Prediction> {
+        let path = path.as_ref();
+        let image = image::open(path).with_context(|| format!("Failed to read image {}", path.display()))?;
+        self.predict_image(&image)
+    }
+
+    pub fn predict_bytes(&self, bytes: &[u8]) -> Result<Prediction> {
+        self.predict_image(&decode_image(bytes)?)
+    }
+
+    pub fn predict_image(&self, image: &DynamicImage) -> Result<Prediction> {
+        let mut predictions = self.predict_batch(std::slice::from_ref(image))?;
+        predictions.pop().ok_or_else(|| anyhow!("No prediction for the image"))
+    }
+
+    /// Classifies the images in one forward pass
+    pub fn predict_batch(&self, images: &[DynamicImage]) -> Result<Vec<Prediction>> {
+        if images.is_empty() {
+            return Ok(Vec::new());
+        }
+        let output = self.run(images);
+        read_predictions(output.probabilities, output.mc)
+    }
+
+    fn run(&self, images: &[DynamicImage]) -> BatchOutput<B> {
+        let Preprocessor { channels, height, width, .. } = self.preprocessor;
+        let pixels: Vec<f32> = images.iter().flat_map(|image| self.preprocessor.process(image)).collect();
+        let input = Tensor::<B, 1>::from_floats(pixels.as_slice(), &self.device).reshape([images.len(), channels, height, width]);
+
+        // One row per view when TTA is requested
+        let (input, num_views) = match self.tta {
+            Some(mode) => mode.augment(input),
+            None => (input, 1),
+        };
+        match (&self.model, self.mc_samples) {
+            (InferenceModel::Float(model), Some(samples)) => {
+                let mc = model.forward_mc_dropout(input, samples);
+                let mutual_information = average_views(mc.mutual_information.unsqueeze_dim(1), num_views);
+                BatchOutput {
+                    probabilities: average_views(mc.mean_probs, num_views),
+                    mc: Some((
+                        samples,
+                        average_views(mc.variance, num_views),
+                        mutual_information.reshape([images.len()]),
+                    )),
+                }
+            }
+            _ => BatchOutput {
+                probabilities: average_views(softmax(self.model.forward(input), 1), num_views),
+                mc: None,
+            },
+        }
+    }
+}
+
+/// Several classifiers, possibly of different architectures and input sizes, run on the same images
+pub struct MalariaEnsemble<B: Backend> {
+    members: Vec<MalariaClassifier<B>>,
+    method: EnsembleMethod,
+}
+
+impl<B: Backend> MalariaEnsemble<B> {
+    pub fn new(members: Vec<MalariaClassifier<B>>, method: EnsembleMethod) -> Result<Self> {
+        if members.is_empty() {
+            bail!("An ensemble needs at least one member");
+        }
+        Ok(Self { members, method })
+    }
+
+    pub fn predict_image(&self, image: &DynamicImage) -> Result<EnsemblePrediction> {
+        let mut predictions = self.predict_batch(std::slice::from_ref(image))?;
+        predictions.pop().ok_or_else(|| anyhow!("No prediction for the image"))
+    }
+
+    pub fn predict_batch(&self, images: &[DynamicImage]) -> Result<Vec<EnsemblePrediction>> {
+        if images.is_empty() {
+            return Ok(Vec::new());
+        }
+        let outputs: Vec<BatchOutput<B>> = self.members.iter().map(|member| member.run(images)).collect();
+
+        // MC dropout statistics are averaged over the members
+        let mc = outputs.first().and_then(|output| output.mc.as_ref()).map(|(samples, _, _)| *samples);
+        let mc = mc.map(|samples| {
+            let (variances, mis): (Vec<_>, Vec<_>) =
+                outputs.iter().filter_map(|output| output.mc.clone()).map(|(_, v, i)| (v, i)).unzip();
+            let variance = Tensor::stack::<3>(variances, 0).mean_dim(0).squeeze_dim(0);
+            let mutual_information = Tensor::stack::<2>(mis, 0).mean_dim(0).reshape([images.len()]);
+            (samples, variance, mutual_information)
+        });
+        let combined = combine(outputs.into_iter().map(|output| output.probabilities).collect(), self.method);
+
+        let predictions = read_predictions(combined.probabilities, mc)?;
+        let disagreement = read(combined.disagreement)?;
+        let variance = read(combined.variance)?;
+        let mutual_information = read(combined.mutual_information)?;
+        Ok(predictions
+            .into_iter()
+            .enumerate()
+            .map(|(i, prediction)| EnsemblePrediction {
+                prediction,
+                members: self.members.len(),
+                method: self.method,
+                disagreement: disagreement[i],
+                variance: [variance[2 * i], variance[2 * i + 1]],
+                mutual_information: mutual_information[i],
+            })
+            .collect())
+    }
+}
+
+fn read_predictions<B: Backend>(
+    probabilities: Tensor<B, 2>,
+    mc: Option<(usize, Tensor<B, 2>, Tensor<B, 1>)>,
+) -> Result<Vec<Prediction>> {
+    let probabilities = read(probabilities)?;
+    let mc = match mc {
+        Some((samples, variance, mutual_information)) => Some((samples, read(variance)?, read(mutual_information)?)),
+        None => None,
+    };
+    Ok(probabilities
+        .chunks_exact(2)
+        .enumerate()
+        .map(|(i, p)| {
+            let probabilities = [p[0], p[1]];
+            Prediction {
+                class: CellClass::from_probabilities(probabilities),
+                probabilities,
+                uncertainty: mc.as_ref().map(|(samples, variance, mutual_information)| McUncertainty {
+                    mc_samples: *samples,
+                    variance: [variance[2 * i], variance[2 * i + 1]],
+                    mutual_information: mutual_information[i],
+                }),
+            }
+        })
+        .collect())
+}
+
+fn read<B: Backend, const D: usize>(tensor: Tensor<B, D>) -> Result<Vec<f32>> {
+    tensor.into_data().to_vec::<f32>().map_err(|e| anyhow!("Failed to read model output: {:?}", e))
+}
+
+/// Model described by `cfg`
+pub fn build_model<B: Backend>(cfg: &ModelConfig, device: &B::Device) -> MalariaCNN<B> {
+    MalariaCNN::new(
+        device,
+        cfg.image_channels,
+        &cfg.architecture,
+        &cfg.conv_blocks,
+        &cfg.fc_units,
+        cfg.num_classes,
+        cfg.dropout_rate,
+    )
+}
+
+/// Forward pass of a loaded bundle
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)] // Built once per model
+pub enum InferenceModel<B: Backend> {
+    Float(MalariaCNN<B>),
+    /// Int8 engine of a quantized bundle
+    Int8(Arc<Int8Model>),
+}
+
+impl<B: Backend> InferenceModel<B> {
+    /// Logits `[batch, classes]`
+    pub fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 2> {
+        match self {
+            Self::Float(model) => model.forward(x),
+            Self::Int8(model) => model.forward(x),
+        }
+    }
+}
+
+/// Loads the checkpoint at `path`, on the int8 engine when it is quantized
+pub fn load_inference_model<B: Backend>(path: &Path, cfg: &ModelConfig, device: &B::Device) -> Result<InferenceModel<B>> {
+    let model = load_model(path, cfg, device)?;
+    Ok(match &cfg.quantization {
+        Some(quantization) => InferenceModel::Int8(Arc::new(Int8Model::new(&model, &quantization.activation_ranges)?)),
+        None => InferenceModel::Float(model),
+    })
+}
+
+/// Loads the checkpoint at `path`; the int8 weights of a quantized checkpoint are expanded to f32
+pub fn load_model<B: Backend>(path: &Path, cfg: &ModelConfig, device: &B::Device) -> Result<MalariaCNN<B>> {
+    cfg.validate()?;
+    let model = build_model(cfg, device).load_record(load_record(path, cfg, device)?);
+    Ok(match cfg.quantization {
+        Some(_) => model.map(&mut Dequantizer),
+        None => model,
+    })
+}
+
+/// Weights saved at `path` for the model described by `cfg`. Checkpoints saved before the conv
+/// stack became configurable (such as the shipped `malaria-model.bin`) are mapped onto the
+/// equivalent conv blocks.
+pub fn load_record<B: Backend>(path: &Path, cfg: &ModelConfig, device: &B::Device) -> Result<MalariaCNNRecord<B>> {
+    let recorder = BinFileRecorder::<FullPrecisionSettings>::new();
+    let error = match recorder.load(path.to_path_buf(), device) {
+        Ok(record) => return Ok(record),
+        Err(e) => e,
+    };
+    match recorder.load::<LegacyMalariaCNNRecord<B>>(path.to_path_buf(), device) {
+        Ok(legacy) => {
+            let legacy_layout = cfg.architecture == Architecture::Custom
+                && cfg.quantization.is_none()
+                && cfg.conv_blocks.iter().all(|block| *block == ConvBlockConfig::new(block.filters));
+            if !legacy_layout {
+                bail!(
+                    "{} uses the legacy layout (3x3 conv, BatchNorm, ReLU and max pooling blocks), which its config does not describe",
+                    path.display()
+                );
+            }
+            let model = build_model(cfg, device)
+                .load_legacy_record(legacy)
+                .with_context(|| format!("Failed to load legacy checkpoint {}", path.display()))?;
+            Ok(model.into_record())
+        }
+        Err(_) => Err(anyhow!("Failed to load checkpoint {}: {:?}", path.display(), error)),
+    }
+}
+
+/// Loads a checkpoint on an autodiff backend, for Grad-CAM. Autodiff cannot hold int8 tensors:
+/// quantized checkpoints are loaded on the inner backend and handed over as an f32 record.
+pub fn load_explain_model<B: AutodiffBackend>(path: &Path, cfg: &ModelConfig, device: &B::Device) -> Result<MalariaCNN<B>> {
+    if cfg.quantization.is_none() {
+        return load_model(path, cfg, device);
+    }
+    let model = load_model::<B::InnerBackend>(path, cfg, device)?;
+    let recorder = BinBytesRecorder::<FullPrecisionSettings>::new();
+    let bytes = recorder.record(model.into_record(), ()).map_err(|e| anyhow!("{:?}", e))?;
+    let record = recorder.load(bytes, device).map_err(|e| anyhow!("{:?}", e))?;
+    Ok(build_model(cfg, device).load_record(record))
+}
+
+struct Dequantizer;
+
+impl<B: Backend> ModuleMapper<B> for Dequantizer {
+    fn map_float<const D: usize>(&mut self, param: Param<Tensor<B, D>>) -> Param<Tensor<B, D>> {
+        param.map(|tensor| tensor.dequantize())
+    }
+}
+
+pub fn decode_image(bytes: &[u8]) -> Result<DynamicImage> {
+    ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .context("Unsupported image format")?
+        .decode()
+        .context("Failed to decode image")
+}
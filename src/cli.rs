@@ -0,0 +1,241 @@
+//! Command-line arguments of the training binary
+
+use std::{path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    ensemble::EnsembleMethod,
+    pruning::{PruningCriterion, PruningOptions},
+    training::ResumeFrom,
+    tta::TtaMode,
+    tuning::SearchStrategy,
+};
+
+/// Subcommand selected on the command line
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Train a model (default when no subcommand is given), optionally resuming a run
+    Train { resume: Option<ResumeFrom> },
+    /// Train one model per fold of a stratified K-fold split
+    CrossValidate { folds: usize, grouped: bool, keep_models: bool },
+    /// Hyperparameter search over the fields listed in a search space file
+    Tune {
+        space: PathBuf,
+        strategy: SearchStrategy,
+        trials: Option<usize>,
+        backend: ComputeBackend,
+        seed: u64,
+    },
+    /// Report validation accuracy of a checkpoint with and without TTA, or of an ensemble
+    /// when `--model` is repeated. `data` is a held-out image folder, required for ensembles.
+    Evaluate { model_paths: Vec<String>, tta: TtaMode, method: EnsembleMethod, data: Option<PathBuf> },
+    /// Write an int8 copy of a checkpoint and compare it with the f32 model
+    Quantize { model_path: String, output: String, calibration_samples: usize },
+    /// Remove the lowest-ranked conv filters and write a smaller model
+    Prune { model_path: String, output: String, options: PruningOptions },
+    /// Classify image files with a model, or an ensemble when `--model` is repeated
+    Predict {
+        model_paths: Vec<String>,
+        images: Vec<PathBuf>,
+        tta: Option<TtaMode>,
+        mc_samples: Option<usize>,
+        method: EnsembleMethod,
+    },
+}
+
+/// Backend the tuning trials are trained on
+#[derive(Debug, Clone, Copy)]
+pub enum ComputeBackend {
+    Cpu,
+    Wgpu,
+}
+
+impl FromStr for ComputeBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "cpu" => Ok(Self::Cpu),
+            "wgpu" => Ok(Self::Wgpu),
+            other => Err(anyhow!("Backend inconnu: {} (attendu: cpu, wgpu)", other)),
+        }
+    }
+}
+
+impl Command {
+    pub fn from_args() -> Result<Self> {
+        let mut args = std::env::args().skip(1).peekable();
+        match args.next().as_deref() {
+            None => Ok(Self::Train { resume: None }),
+            Some("train") => {
+                let mut resume = None;
+                while let Some(flag) = args.next() {
+                    match flag.as_str() {
+                        // `--resume` alone picks the latest checkpoint, `--resume N` a given epoch
+                        "--resume" => {
+                            resume = Some(match args.next_if(|v| !v.starts_with("--")) {
+                                Some(epoch) => ResumeFrom::Epoch(
+                                    epoch.parse().map_err(|_| anyhow!("Époque invalide pour --resume: {}", epoch))?,
+                                ),
+                                None => ResumeFrom::Latest,
+                            })
+                        }
+                        other => bail!("Option inconnue pour train: {}", other),
+                    }
+                }
+                Ok(Self::Train { resume })
+            }
+            Some("cross-validate") => {
+                let mut folds = 5;
+                let mut grouped = false;
+                let mut keep_models = false;
+                while let Some(flag) = args.next() {
+                    match flag.as_str() {
+                        "--folds" => {
+                            let value = next_value(&mut args, "--folds")?;
+                            folds = value.parse().map_err(|_| anyhow!("Nombre de folds invalide: {}", value))?;
+                        }
+                        // Keeps all cells of a slide in the same fold
+                        "--group-by-slide" => grouped = true,
+                        "--keep-models" => keep_models = true,
+                        other => bail!("Option inconnue pour cross-validate: {}", other),
+                    }
+                }
+                Ok(Self::CrossValidate { folds, grouped, keep_models })
+            }
+            Some("tune") => {
+                let mut space = None;
+                let mut strategy = SearchStrategy::Random;
+                let mut trials = None;
+                let mut backend = ComputeBackend::Wgpu;
+                let mut seed = 42;
+                while let Some(flag) = args.next() {
+                    match flag.as_str() {
+                        "--space" => space = Some(PathBuf::from(next_value(&mut args, "--space")?)),
+                        "--strategy" => strategy = next_value(&mut args, "--strategy")?.parse()?,
+                        "--trials" => {
+                            let value = next_value(&mut args, "--trials")?;
+                            trials = Some(value.parse().map_err(|_| anyhow!("Nombre d'essais invalide: {}", value))?);
+                        }
+                        "--backend" => backend = next_value(&mut args, "--backend")?.parse()?,
+                        "--seed" => {
+                            let value = next_value(&mut args, "--seed")?;
+                            seed = value.parse().map_err(|_| anyhow!("Graine invalide: {}", value))?;
+                        }
+                        other => bail!("Option inconnue pour tune: {}", other),
+                    }
+                }
+                let space = space.ok_or_else(|| anyhow!("tune demande --space <fichier.json>"))?;
+                Ok(Self::Tune { space, strategy, trials, backend, seed })
+            }
+            Some("evaluate") => {
+                let mut model_paths = Vec::new();
+                let mut tta = TtaMode::Dihedral;
+                let mut method = EnsembleMethod::Mean;
+                let mut data = None;
+                while let Some(flag) = args.next() {
+                    match flag.as_str() {
+                        "--model" => model_paths.push(next_value(&mut args, "--model")?),
+                        "--tta" => tta = next_value(&mut args, "--tta")?.parse()?,
+                        "--ensemble" => method = next_value(&mut args, "--ensemble")?.parse()?,
+                        "--data" => data = Some(PathBuf::from(next_value(&mut args, "--data")?)),
+                        other => bail!("Option inconnue pour evaluate: {}", other),
+                    }
+                }
+                if model_paths.is_empty() {
+                    model_paths.push("./malaria-model".to_string());
+                }
+                Ok(Self::Evaluate { model_paths, tta, method, data })
+            }
+            Some("quantize") => {
+                let mut model_path = "./malaria-model".to_string();
+                let mut output = "./malaria-model-int8".to_string();
+                let mut calibration_samples = 256;
+                while let Some(flag) = args.next() {
+                    match flag.as_str() {
+                        "--model" => model_path = next_value(&mut args, "--model")?,
+                        "--output" => output = next_value(&mut args, "--output")?,
+                        "--calibration-samples" => {
+                            let value = next_value(&mut args, "--calibration-samples")?;
+                            calibration_samples =
+                                value.parse().map_err(|_| anyhow!("Nombre d'images invalide: {}", value))?;
+                        }
+                        other => bail!("Option inconnue pour quantize: {}", other),
+                    }
+                }
+                Ok(Self::Quantize { model_path, output, calibration_samples })
+            }
+            Some("prune") => {
+                let mut model_path = "./malaria-model".to_string();
+                let mut output = "./malaria-model-pruned".to_string();
+                let mut options = PruningOptions {
+                    ratio: 0.3,
+                    criterion: PruningCriterion::L1Norm,
+                    fine_tune_epochs: 0,
+                    calibration_samples: 256,
+                };
+                while let Some(flag) = args.next() {
+                    match flag.as_str() {
+                        "--model" => model_path = next_value(&mut args, "--model")?,
+                        "--output" => output = next_value(&mut args, "--output")?,
+                        "--ratio" => {
+                            let value = next_value(&mut args, "--ratio")?;
+                            options.ratio = value.parse().map_err(|_| anyhow!("Ratio invalide: {}", value))?;
+                        }
+                        "--criterion" => options.criterion = next_value(&mut args, "--criterion")?.parse()?,
+                        "--fine-tune-epochs" => {
+                            let value = next_value(&mut args, "--fine-tune-epochs")?;
+                            options.fine_tune_epochs =
+                                value.parse().map_err(|_| anyhow!("Nombre d'époques invalide: {}", value))?;
+                        }
+                        "--calibration-samples" => {
+                            let value = next_value(&mut args, "--calibration-samples")?;
+                            options.calibration_samples =
+                                value.parse().map_err(|_| anyhow!("Nombre d'images invalide: {}", value))?;
+                        }
+                        other => bail!("Option inconnue pour prune: {}", other),
+                    }
+                }
+                Ok(Self::Prune { model_path, output, options })
+            }
+            Some("predict") => {
+                let mut model_paths = Vec::new();
+                let mut images = Vec::new();
+                let mut tta = None;
+                let mut mc_samples = None;
+                let mut method = EnsembleMethod::Mean;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--model" => model_paths.push(next_value(&mut args, "--model")?),
+                        "--tta" => tta = Some(next_value(&mut args, "--tta")?.parse()?),
+                        "--mc-samples" => {
+                            let value = next_value(&mut args, "--mc-samples")?;
+                            mc_samples = match value.parse() {
+                                Ok(0) | Err(_) => bail!("Nombre de passes invalide: {}", value),
+                                Ok(n) => Some(n),
+                            };
+                        }
+                        "--ensemble" => method = next_value(&mut args, "--ensemble")?.parse()?,
+                        flag if flag.starts_with("--") => bail!("Option inconnue pour predict: {}", flag),
+                        image => images.push(PathBuf::from(image)),
+                    }
+                }
+                if images.is_empty() {
+                    bail!("predict demande au moins une image");
+                }
+                if model_paths.is_empty() {
+                    model_paths.push("./malaria-model".to_string());
+                }
+                Ok(Self::Predict { model_paths, images, tta, mc_samples, method })
+            }
+            Some(other) => {
+                bail!("Commande inconnue: {} (attendu: train, cross-validate, tune, evaluate, quantize, prune, predict)", other)
+            }
+        }
+    }
+}
+
+fn next_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String> {
+    args.next().ok_or_else(|| anyhow!("Valeur manquante pour {}", flag))
+}
@@ -1,7 +1,15 @@
 //! CNN model configuration balanced for quality/speed
 
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context};
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    architecture::{Architecture, ConvBlockConfig, Normalization},
+    preprocessing::{PreprocessingConfig, Preprocessor},
+};
+
 /// Full CNN model configuration for malaria detection
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelConfig {
@@ -11,16 +19,16 @@ pub struct ModelConfig {
     pub image_height: usize,
     /// Number of channels (3 for RGB, 1 for grayscale)
     pub image_channels: usize,
-    /// Number of filters for the first convolutional layer
-    pub conv1_filters: usize,
-    /// Number of filters for the second convolutional layer
-    pub conv2_filters: usize,
-    /// Number of filters for the third convolutional layer
-    pub conv3_filters: usize,
-    /// Units for the first fully-connected layer
-    pub fc1_units: usize,
-    /// Units for the second fully-connected layer
-    pub fc2_units: usize,
+    /// Resize filter and pixel normalization, applied identically in training and serving
+    /// (bundles written before this field use the defaults)
+    #[serde(default)]
+    pub preprocessing: PreprocessingConfig,
+    /// Feature extractor; `conv_blocks` only applies to `Custom`
+    pub architecture: Architecture,
+    /// Convolutional blocks, applied in order
+    pub conv_blocks: Vec<ConvBlockConfig>,
+    /// Units of the hidden fully-connected layers, before the output layer
+    pub fc_units: Vec<usize>,
     /// Number of output classes (2: malaria/non-malaria)
     pub num_classes: usize,
     /// Dropout rate for regularization
@@ -41,6 +49,201 @@ pub struct ModelConfig {
     pub num_workers: usize,
     /// Gradient accumulation steps
     pub grad_accum_steps: usize,
+    /// Learning-rate schedule applied after the warmup
+    pub lr_schedule: LrSchedule,
+    /// Epochs of linear warmup up to `learning_rate` (0 disables warmup)
+    pub lr_warmup_epochs: usize,
+    /// Validation metric used for early stopping and to pick the exported epoch
+    pub selection_metric: SelectionMetric,
+    /// Stop after this many epochs without improvement (`None` trains for `num_epochs`)
+    pub early_stopping_patience: Option<usize>,
+    /// Minimum change of the selection metric that counts as an improvement
+    /// (accuracy is in percent)
+    pub early_stopping_min_delta: f64,
+    /// Number of best checkpoints kept on disk (the latest one is always kept too)
+    pub keep_best_checkpoints: usize,
+    /// Optimizer and its hyperparameters
+    pub optimizer: OptimizerConfig,
+    /// Gradient clipping applied before each optimizer step (`None` disables it)
+    pub grad_clipping: Option<GradClipping>,
+    /// Loss function options
+    pub loss: LossConfig,
+    /// How training items are drawn each epoch
+    pub sampling: Sampling,
+    /// Start from pretrained weights (`None` trains from scratch)
+    pub fine_tune: Option<FineTuneConfig>,
+    /// Train against the logits of a larger teacher model as well as the labels
+    pub distillation: Option<DistillationConfig>,
+    /// Export averaged weights instead of the best epoch (`None` keeps the best epoch)
+    pub weight_averaging: Option<WeightAveraging>,
+    /// Set on int8 models written by the `quantize` command
+    pub quantization: Option<QuantizationConfig>,
+}
+
+impl ModelConfig {
+    /// Reads the configuration saved next to a checkpoint (`<model>.json`)
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Lecture de {}", path.display()))?;
+        let config: Self =
+            serde_json::from_str(&text).with_context(|| format!("Configuration invalide: {}", path.display()))?;
+        config.validate().with_context(|| format!("Configuration invalide: {}", path.display()))?;
+        Ok(config)
+    }
+
+    /// Rejects architectures that cannot be built
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.architecture == Architecture::Custom && self.conv_blocks.is_empty() {
+            bail!("L'architecture custom demande au moins un bloc dans conv_blocks");
+        }
+        if self.architecture == Architecture::Custom {
+            for (i, block) in self.conv_blocks.iter().enumerate() {
+                if let Normalization::GroupNorm { groups } = block.norm {
+                    if groups == 0 || block.filters % groups != 0 {
+                        bail!("Bloc {}: {} groupes ne divisent pas {} filtres", i + 1, groups, block.filters);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Preprocessing of the model input described by this config
+    pub fn preprocessor(&self) -> anyhow::Result<Preprocessor> {
+        Preprocessor::new(self.image_height, self.image_width, self.image_channels, &self.preprocessing)
+    }
+}
+
+/// Int8 model: conv and linear weights stored as int8, biases and normalization in f32, run by
+/// the int8 engine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuantizationConfig {
+    /// Calibrated `[min, max]` of the input of each conv block and classifier layer, in forward order
+    pub activation_ranges: Vec<[f32; 2]>,
+    /// Number of training images the ranges were calibrated on
+    pub calibration_samples: usize,
+}
+
+/// Averaged weights exported as the final model, after re-estimating BatchNorm statistics
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WeightAveraging {
+    /// Exponential moving average of the parameters, updated after every optimizer step
+    Ema { decay: f64 },
+    /// Mean of the checkpoints of the last `epochs` epochs (stochastic weight averaging)
+    Swa { epochs: usize },
+}
+
+/// Knowledge distillation from a trained teacher checkpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DistillationConfig {
+    /// Teacher weights (`.bin`); its `ModelConfig` is read from the `.json` next to it
+    pub teacher_path: String,
+    /// Softmax temperature applied to teacher and student logits in the KL term
+    pub temperature: f32,
+    /// Weight of the KL term; the hard-label loss gets `1 - alpha`
+    pub alpha: f32,
+}
+
+/// Pretrained weights and how the backbone is trained on top of them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FineTuneConfig {
+    /// `.bin` checkpoint of this project, or PyTorch `.pt` state dict
+    pub pretrained_path: String,
+    /// Regex and replacement applied in order to PyTorch parameter names
+    pub key_remap: Vec<(String, String)>,
+    /// Key of the state dict inside the PyTorch file (e.g. `state_dict`), when nested
+    pub top_level_key: Option<String>,
+    /// Epochs during which the backbone weights are not updated, only the classifier head.
+    /// BatchNorm running statistics still adapt to the new images.
+    pub freeze_backbone_epochs: usize,
+    /// Learning rate of the backbone relative to the head's
+    pub backbone_lr_scale: f64,
+}
+
+/// Sampling of the training set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sampling {
+    /// Every item once per epoch, shuffled
+    Uniform,
+    /// Minority classes repeated until every class is as frequent as the largest one
+    Oversample,
+    /// Batches with (nearly) the same number of items of each class; the epoch keeps the dataset size
+    BalancedBatches,
+}
+
+/// Cross-entropy variants used as training (and validation) loss
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LossConfig {
+    /// Weight of each class in the batch loss
+    pub class_weights: ClassWeights,
+    /// Focusing parameter of the focal loss (0 gives plain cross-entropy)
+    pub focal_gamma: f32,
+    /// Probability mass spread uniformly over all classes in the targets (0 disables smoothing)
+    pub label_smoothing: f32,
+}
+
+/// Per-class loss weights
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClassWeights {
+    /// Every class counts the same
+    #[default]
+    Uniform,
+    /// Inverse of each class frequency in the training split
+    Balanced,
+    /// One weight per class, indexed by label
+    Explicit(Vec<f32>),
+}
+
+/// Optimizer used for training; the learning rate comes from `learning_rate` and `lr_schedule`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OptimizerConfig {
+    /// Adam with L2 weight decay added to the gradients (0 disables it)
+    Adam { beta_1: f32, beta_2: f32, epsilon: f32, weight_decay: f32 },
+    /// Adam with decoupled weight decay
+    AdamW { beta_1: f32, beta_2: f32, epsilon: f32, weight_decay: f32 },
+    /// Stochastic gradient descent (`momentum` = 0 disables momentum)
+    Sgd { momentum: f64, dampening: f64, nesterov: bool, weight_decay: f32 },
+    /// RMSprop with smoothing constant `alpha`
+    RmsProp { alpha: f32, momentum: f32, epsilon: f32, centered: bool, weight_decay: f32 },
+}
+
+/// Gradient clipping threshold
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GradClipping {
+    /// Rescale each parameter's gradient so that its L2 norm is at most this value
+    Norm(f32),
+    /// Clamp every gradient component to `[-value, value]`
+    Value(f32),
+}
+
+/// Validation metric driving model selection
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionMetric {
+    /// Lowest validation loss
+    ValidLoss,
+    /// Highest validation accuracy
+    ValidAccuracy,
+}
+
+/// Learning-rate schedule, starting from `ModelConfig::learning_rate`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LrSchedule {
+    /// Fixed learning rate
+    Constant,
+    /// Multiply the learning rate by `gamma` every `step_epochs` epochs
+    Step { step_epochs: usize, gamma: f64 },
+    /// Multiply the learning rate by `gamma` every epoch (applied smoothly per iteration)
+    Exponential { gamma: f64 },
+    /// Cosine annealing down to `min_lr`, restarting every `period_epochs` epochs
+    CosineRestarts { period_epochs: usize, min_lr: f64 },
+    /// Ramp up to `max_lr` over `pct_start` of training, then anneal to `learning_rate / final_div_factor`
+    OneCycle { max_lr: f64, pct_start: f64, final_div_factor: f64 },
 }
 
 impl Default for ModelConfig {
@@ -50,11 +253,14 @@ impl Default for ModelConfig {
             image_width: 128,
             image_height: 128,
             image_channels: 3,
-            conv1_filters: 16,
-            conv2_filters: 32,
-            conv3_filters: 64,
-            fc1_units: 128,
-            fc2_units: 64,
+            preprocessing: PreprocessingConfig::default(),
+            architecture: Architecture::Custom,
+            conv_blocks: vec![
+                ConvBlockConfig::new(16),
+                ConvBlockConfig::new(32),
+                ConvBlockConfig::new(64),
+            ],
+            fc_units: vec![128, 64],
             num_classes: 2,
             dropout_rate: 0.3,
             learning_rate: 0.001,
@@ -66,6 +272,54 @@ impl Default for ModelConfig {
             use_cache: true, // ✅ Cache enabled for performance
             num_workers: 2,  // ✅ Conservative value for stability
             grad_accum_steps: 1,
+            lr_schedule: LrSchedule::Constant,
+            lr_warmup_epochs: 0,
+            selection_metric: SelectionMetric::ValidLoss,
+            early_stopping_patience: None,
+            early_stopping_min_delta: 0.0,
+            keep_best_checkpoints: 3,
+            optimizer: OptimizerConfig::Adam {
+                beta_1: 0.9,
+                beta_2: 0.999,
+                epsilon: 1e-5,
+                weight_decay: 1e-4,
+            },
+            grad_clipping: None,
+            loss: LossConfig::default(),
+            sampling: Sampling::Uniform,
+            fine_tune: None,
+            distillation: None,
+            weight_averaging: None,
+            quantization: None,
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rejects_unbuildable_custom_stacks() {
+        assert!(ModelConfig::default().validate().is_ok());
+
+        let empty = ModelConfig { conv_blocks: Vec::new(), ..ModelConfig::default() };
+        assert!(empty.validate().is_err());
+        let resnet = ModelConfig { architecture: Architecture::ResNet18 { base_width: 8 }, ..empty };
+        assert!(resnet.validate().is_ok());
+
+        let mut groups = ModelConfig::default();
+        groups.conv_blocks[0].norm = Normalization::GroupNorm { groups: 0 };
+        assert!(groups.validate().is_err());
+    }
+
+    #[test]
+    fn load_rejects_empty_conv_blocks() {
+        let path = std::env::temp_dir().join(format!("config-empty-blocks-{}.json", std::process::id()));
+        let mut value = serde_json::to_value(ModelConfig::default()).unwrap();
+        value["conv_blocks"] = serde_json::json!([]);
+        fs::write(&path, value.to_string()).unwrap();
+        let loaded = ModelConfig::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Fold assignment for K-fold cross-validation
+
+use std::{collections::HashMap, path::Path};
+
+/// Slide an image was cropped from: file name up to the first `_`
+/// (e.g. `C33P1thinF` for `C33P1thinF_IMG_20150619_114756a_cell_179.png`)
+pub fn slide_id(path: &Path) -> String {
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    name.split('_').next().unwrap_or_default().to_string()
+}
+
+/// Splits item indices into `num_folds` validation folds with the same class proportions.
+///
+/// With `groups`, items sharing a group always land in the same fold; groups are then
+/// placed greedily, largest first, in the fold that stays least filled relative to each
+/// class total (smallest sum of squared fill ratios, so one class does not pile up in a fold).
+pub fn stratified_folds(labels: &[u8], groups: Option<&[String]>, num_folds: usize) -> Vec<Vec<usize>> {
+    let num_labels = labels.iter().map(|&l| l as usize + 1).max().unwrap_or(0);
+    let mut folds = vec![Vec::new(); num_folds];
+
+    let Some(groups) = groups else {
+        // Items are already shuffled, so dealing each class round-robin is enough
+        let mut dealt = vec![0usize; num_labels];
+        for (index, &label) in labels.iter().enumerate() {
+            folds[dealt[label as usize] % num_folds].push(index);
+            dealt[label as usize] += 1;
+        }
+        return folds;
+    };
+
+    let mut members: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, group) in groups.iter().enumerate() {
+        members.entry(group.as_str()).or_default().push(index);
+    }
+    let mut members: Vec<(&str, Vec<usize>)> = members.into_iter().collect();
+    members.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(b.0)));
+
+    let mut class_totals = vec![0usize; num_labels];
+    for &label in labels {
+        class_totals[label as usize] += 1;
+    }
+    let mut fold_counts = vec![vec![0usize; num_labels]; num_folds];
+
+    for (_, indices) in members {
+        let mut group_counts = vec![0usize; num_labels];
+        for &index in &indices {
+            group_counts[labels[index] as usize] += 1;
+        }
+        let fill = |fold: usize| -> f64 {
+            (0..num_labels)
+                .filter(|&c| class_totals[c] > 0)
+                .map(|c| ((fold_counts[fold][c] + group_counts[c]) as f64 / class_totals[c] as f64).powi(2))
+                .sum()
+        };
+        let target = (0..num_folds)
+            .min_by(|&a, &b| fill(a).total_cmp(&fill(b)))
+            .unwrap_or(0);
+        for c in 0..num_labels {
+            fold_counts[target][c] += group_counts[c];
+        }
+        folds[target].extend(indices);
+    }
+
+    for fold in &mut folds {
+        fold.sort_unstable();
+    }
+    folds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every index lands in exactly one fold
+    fn assert_partition(folds: &[Vec<usize>], len: usize) {
+        let mut all: Vec<usize> = folds.iter().flatten().copied().collect();
+        all.sort_unstable();
+        assert_eq!(all, (0..len).collect::<Vec<_>>());
+    }
+
+    fn class_count(fold: &[usize], labels: &[u8], label: u8) -> usize {
+        fold.iter().filter(|&&i| labels[i] == label).count()
+    }
+
+    #[test]
+    fn slide_id_is_the_name_before_the_first_underscore() {
+        let path = Path::new("data/Parasitized/C33P1thinF_IMG_20150619_114756a_cell_179.png");
+        assert_eq!(slide_id(path), "C33P1thinF");
+        assert_eq!(slide_id(Path::new("cell.png")), "cell");
+    }
+
+    #[test]
+    fn folds_are_disjoint_and_stratified() {
+        // 10 of class 1, 30 of class 0
+        let labels: Vec<u8> = (0..40).map(|i| u8::from(i % 4 == 0)).collect();
+        let folds = stratified_folds(&labels, None, 5);
+        assert_partition(&folds, labels.len());
+        for fold in &folds {
+            assert_eq!(class_count(fold, &labels, 1), 2);
+            assert_eq!(class_count(fold, &labels, 0), 6);
+        }
+    }
+
+    #[test]
+    fn cells_of_a_slide_stay_in_one_fold() {
+        // 8 slides of 3 cells each, half of them parasitized
+        let labels: Vec<u8> = (0..24).map(|i| u8::from(i / 3 % 2 == 0)).collect();
+        let groups: Vec<String> = (0..24).map(|i| format!("slide{}", i / 3)).collect();
+        let folds = stratified_folds(&labels, Some(&groups), 4);
+        assert_partition(&folds, labels.len());
+        for fold in &folds {
+            let slides: Vec<&str> = fold.iter().map(|&i| groups[i].as_str()).collect();
+            for slide in &slides {
+                assert_eq!(slides.iter().filter(|s| *s == slide).count(), 3, "{} is split across folds", slide);
+            }
+            assert_eq!(class_count(fold, &labels, 1), 3);
+            assert_eq!(class_count(fold, &labels, 0), 3);
+        }
+    }
+}
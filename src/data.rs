@@ -3,10 +3,10 @@ use burn::{
     data::{dataloader::batcher::Batcher, dataset::Dataset},
     tensor::{backend::Backend, Int, Tensor},
 };
-use image::{ImageReader, imageops::FilterType};
 use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fs, path::{Path, PathBuf}, sync::Arc};
+use crate::preprocessing::Preprocessor;
 
 /// Dataset item containing image path and label
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,16 +21,14 @@ pub struct MalariaDataset {
     pub images: Vec<PathBuf>,
     pub labels: Vec<u8>,
     pub cache: Option<Arc<HashMap<PathBuf, Vec<f32>>>>,
-    pub target_height: usize,
-    pub target_width: usize,
+    pub preprocessor: Preprocessor,
     pub use_cache: bool,
 }
 
 impl MalariaDataset {
     pub fn new<P: AsRef<Path>>(
         root_dir: P,
-        target_height: usize,
-        target_width: usize,
+        preprocessor: Preprocessor,
         use_cache: bool,
     ) -> Result<Self> {
         let root_dir = root_dir.as_ref();
@@ -59,7 +57,7 @@ impl MalariaDataset {
         Self::load_images_from_dir(&uninfected_dir, 0, &mut images, &mut labels)?;
 
         let mut rng = StdRng::seed_from_u64(42);
-        let mut combined: Vec<_> = images.into_iter().zip(labels.into_iter()).collect();
+        let mut combined: Vec<_> = images.into_iter().zip(labels).collect();
         combined.shuffle(&mut rng);
         let (shuffled_images, shuffled_labels): (Vec<_>, Vec<_>) = combined.into_iter().unzip();
 
@@ -71,8 +69,7 @@ impl MalariaDataset {
             images: shuffled_images,
             labels: shuffled_labels,
             cache: None,
-            target_height,
-            target_width,
+            preprocessor,
             use_cache,
         };
 
@@ -94,7 +91,7 @@ impl MalariaDataset {
     fn build_cache(&self) -> HashMap<PathBuf, Vec<f32>> {
         let mut cache = HashMap::with_capacity(self.images.len());
         for path in &self.images {
-            if let Ok(data) = Self::load_and_preprocess_image_raw(path, self.target_height, self.target_width) {
+            if let Ok(data) = self.preprocessor.load(path) {
                 cache.insert(path.clone(), data);
             }
         }
@@ -129,35 +126,6 @@ impl MalariaDataset {
         Ok(())
     }
 
-    /// ✅ CPU ONLY - Optimized preprocessing
-    pub fn load_and_preprocess_image_raw(
-        path: &Path,
-        target_height: usize,
-        target_width: usize,
-    ) -> Result<Vec<f32>> {
-        let img = ImageReader::open(path)?
-            .decode()?
-            .resize_exact(
-                target_width as u32,
-                target_height as u32,
-                FilterType::Triangle,
-            );
-
-        let rgb_img = img.to_rgb8();
-        let raw_pixels = rgb_img.into_raw();
-        let frame_size = target_height * target_width;
-        let total_size = frame_size * 3;
-
-        let mut chw_data = vec![0.0; total_size];
-        for (i, chunk) in raw_pixels.chunks_exact(3).enumerate() {
-            chw_data[i] = chunk[0] as f32 / 255.0;
-            chw_data[i + frame_size] = chunk[1] as f32 / 255.0;
-            chw_data[i + 2 * frame_size] = chunk[2] as f32 / 255.0;
-        }
-
-        Ok(chw_data)
-    }
-
     pub fn get_image_data(&self, index: usize) -> Result<Vec<f32>> {
         if index >= self.images.len() {
             return Err(anyhow!("Index {} hors limites", index));
@@ -170,9 +138,9 @@ impl MalariaDataset {
                     return Ok(data.clone());
                 }
             }
-            Self::load_and_preprocess_image_raw(path, self.target_height, self.target_width)
+            self.preprocessor.load(path)
         } else {
-            Self::load_and_preprocess_image_raw(path, self.target_height, self.target_width)
+            self.preprocessor.load(path)
         }
     }
 
@@ -201,8 +169,7 @@ impl MalariaDataset {
             images: train_images,
             labels: train_labels,
             cache: self.cache.clone(),
-            target_height: self.target_height,
-            target_width: self.target_width,
+            preprocessor: self.preprocessor.clone(),
             use_cache: self.use_cache,
         };
 
@@ -210,14 +177,24 @@ impl MalariaDataset {
             images: valid_images,
             labels: valid_labels,
             cache: self.cache.clone(),
-            target_height: self.target_height,
-            target_width: self.target_width,
+            preprocessor: self.preprocessor.clone(),
             use_cache: self.use_cache,
         };
 
         (train_ds, valid_ds)
     }
 
+    /// Dataset restricted to the given item indices, sharing the image cache
+    pub fn subset(&self, indices: &[usize]) -> Self {
+        Self {
+            images: indices.iter().map(|&i| self.images[i].clone()).collect(),
+            labels: indices.iter().map(|&i| self.labels[i]).collect(),
+            cache: self.cache.clone(),
+            preprocessor: self.preprocessor.clone(),
+            use_cache: self.use_cache,
+        }
+    }
+
     pub fn get(&self, index: usize) -> Option<MalariaItem> {
         if index < self.images.len() {
             Some(MalariaItem {
@@ -245,20 +222,20 @@ impl Dataset<MalariaItem> for MalariaDataset {
 pub struct MalariaBatch<B: Backend> {
     pub images: Tensor<B, 4>,
     pub labels: Tensor<B, 1, Int>,
+    /// Teacher predictions for the images, when distilling
+    pub teacher_logits: Option<Tensor<B, 2>>,
 }
 
 /// ✅ FIXED BATCHER - GOLDEN RULE RESPECTED
 pub struct MalariaBatcher<B: Backend> {
-    pub image_height: usize,
-    pub image_width: usize,
+    pub preprocessor: Preprocessor,
     _phantom: std::marker::PhantomData<B>,
 }
 
 impl<B: Backend> MalariaBatcher<B> {
-    pub fn new(image_height: usize, image_width: usize) -> Self {
+    pub fn new(preprocessor: Preprocessor) -> Self {
         Self {
-            image_height,
-            image_width,
+            preprocessor,
             _phantom: std::marker::PhantomData,
         }
     }
@@ -269,20 +246,16 @@ impl<B: Backend> Batcher<B, MalariaItem, MalariaBatch<B>> for MalariaBatcher<B>
     /// ✅ CRITICAL FIX: Use ONLY the provided device
     fn batch(&self, items: Vec<MalariaItem>, device: &B::Device) -> MalariaBatch<B> {
         let batch_size = items.len();
-        let expected_size = batch_size * 3 * self.image_height * self.image_width;
+        let expected_size = batch_size * self.preprocessor.image_size();
         
         let mut images_data = Vec::with_capacity(expected_size);
         let mut labels_data = Vec::with_capacity(batch_size);
 
-        let default_image = vec![0.0; self.image_height * self.image_width * 3];
+        let default_image = vec![0.0; self.preprocessor.image_size()];
 
         // ✅ Preprocessing on CPU
         for item in items {
-            let image_data = match MalariaDataset::load_and_preprocess_image_raw(
-                Path::new(&item.image_path),
-                self.image_height,
-                self.image_width,
-            ) {
+            let image_data = match self.preprocessor.load(Path::new(&item.image_path)) {
                 Ok(data) => data,
                 Err(e) => {
                     eprintln!("⚠️  Erreur chargement {}: {}", item.image_path, e);
@@ -304,12 +277,14 @@ impl<B: Backend> Batcher<B, MalariaItem, MalariaBatch<B>> for MalariaBatcher<B>
 
         // ✅ GPU TRANSFER with the provided device (NEVER use Device::default())
         let images_tensor_1d = Tensor::<B, 1>::from_floats(images_data.as_slice(), device);
-        let images_tensor = images_tensor_1d.reshape([batch_size, 3, self.image_height, self.image_width]);
+        let Preprocessor { channels, height, width, .. } = self.preprocessor;
+        let images_tensor = images_tensor_1d.reshape([batch_size, channels, height, width]);
         let labels_tensor = Tensor::<B, 1, Int>::from_ints(labels_data.as_slice(), device);
 
         MalariaBatch {
             images: images_tensor,
             labels: labels_tensor,
+            teacher_logits: None,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,90 @@
+//! Knowledge distillation: a trained teacher annotates each training batch with its logits
+
+use std::{path::Path, sync::Mutex};
+
+use anyhow::{bail, Context, Result};
+use burn::{
+    data::dataloader::batcher::Batcher,
+    module::Module,
+    tensor::{backend::AutodiffBackend, Tensor},
+};
+use crate::{
+    classifier::load_record,
+    config::{DistillationConfig, ModelConfig},
+    data::{MalariaBatch, MalariaBatcher, MalariaItem},
+    malaria_cnn::MalariaCNN,
+};
+
+/// Loads the teacher from its checkpoint and the `ModelConfig` saved next to it.
+///
+/// The teacher sees the student's batches, so the input size, preprocessing and classes must match.
+pub fn load_teacher<B: AutodiffBackend>(
+    config: &ModelConfig,
+    distillation: &DistillationConfig,
+    device: &B::Device,
+) -> Result<MalariaCNN<B::InnerBackend>> {
+    let path = Path::new(&distillation.teacher_path).with_extension("");
+    let bundle = path.with_extension("json");
+    let teacher = ModelConfig::load(&bundle).context("Configuration du professeur introuvable")?;
+    if (teacher.image_height, teacher.image_width, teacher.image_channels)
+        != (config.image_height, config.image_width, config.image_channels)
+    {
+        bail!(
+            "Le professeur attend des images {}x{}x{}, l'élève {}x{}x{}",
+            teacher.image_width,
+            teacher.image_height,
+            teacher.image_channels,
+            config.image_width,
+            config.image_height,
+            config.image_channels
+        );
+    }
+    if teacher.preprocessing != config.preprocessing {
+        bail!(
+            "Le professeur a été entraîné avec un autre prétraitement ({:?}, l'élève {:?})",
+            teacher.preprocessing,
+            config.preprocessing
+        );
+    }
+    if teacher.num_classes != config.num_classes {
+        bail!("Le professeur a {} classes, l'élève {}", teacher.num_classes, config.num_classes);
+    }
+
+    let record = load_record(&path, &teacher, device).context("Lecture du professeur")?;
+    println!("🧑‍🏫 Professeur: {} ({:?})", distillation.teacher_path, teacher.architecture);
+    Ok(MalariaCNN::new(
+        device,
+        teacher.image_channels,
+        &teacher.architecture,
+        &teacher.conv_blocks,
+        &teacher.fc_units,
+        teacher.num_classes,
+        teacher.dropout_rate,
+    )
+    .load_record(record))
+}
+
+/// Training batcher; when a teacher is set, it runs in eval mode on each batch and its logits
+/// are attached for the distillation loss
+pub struct DistillationBatcher<B: AutodiffBackend> {
+    batcher: MalariaBatcher<B>,
+    /// Modules are not `Sync`; the loader workers take turns with the teacher
+    teacher: Option<Mutex<MalariaCNN<B::InnerBackend>>>,
+}
+
+impl<B: AutodiffBackend> DistillationBatcher<B> {
+    pub fn new(batcher: MalariaBatcher<B>, teacher: Option<MalariaCNN<B::InnerBackend>>) -> Self {
+        Self { batcher, teacher: teacher.map(Mutex::new) }
+    }
+}
+
+impl<B: AutodiffBackend> Batcher<B, MalariaItem, MalariaBatch<B>> for DistillationBatcher<B> {
+    fn batch(&self, items: Vec<MalariaItem>, device: &B::Device) -> MalariaBatch<B> {
+        let mut batch = self.batcher.batch(items, device);
+        if let Some(teacher) = &self.teacher {
+            let logits = teacher.lock().unwrap().forward(batch.images.clone().inner());
+            batch.teacher_logits = Some(Tensor::from_inner(logits));
+        }
+        batch
+    }
+}
@@ -0,0 +1,141 @@
+//! Combination of the predictions of several models on the same images
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use burn::tensor::{backend::Backend, Tensor};
+use serde::{Deserialize, Serialize};
+
+/// How member predictions are merged into the ensemble prediction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnsembleMethod {
+    /// Mean of the member probabilities
+    Mean,
+    /// Share of the members predicting each class
+    Vote,
+}
+
+impl FromStr for EnsembleMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "mean" => Ok(Self::Mean),
+            "vote" => Ok(Self::Vote),
+            other => Err(anyhow!("Méthode d'ensemble inconnue: {} (attendu: mean, vote)", other)),
+        }
+    }
+}
+
+/// Ensemble prediction for a batch, with the spread of the members as uncertainty
+pub struct EnsembleOutput<B: Backend> {
+    /// `[batch, classes]`: mean probabilities or vote shares, depending on the method
+    pub probabilities: Tensor<B, 2>,
+    /// `[batch, classes]` variance of the member probabilities
+    pub variance: Tensor<B, 2>,
+    /// `[batch]` fraction of the members not voting for the majority class
+    pub disagreement: Tensor<B, 1>,
+    /// `[batch]` entropy of the mean minus mean member entropy
+    pub mutual_information: Tensor<B, 1>,
+}
+
+/// Combines member probabilities (each `[batch, classes]`, same images in the same order)
+pub fn combine<B: Backend>(members: Vec<Tensor<B, 2>>, method: EnsembleMethod) -> EnsembleOutput<B> {
+    assert!(!members.is_empty(), "an ensemble needs at least one member");
+    let [batch_size, num_classes] = members[0].dims();
+    let num_members = members.len();
+
+    // [members, batch, classes]
+    let probs = Tensor::stack::<3>(members, 0);
+    let mean_probs = probs.clone().mean_dim(0);
+    let variance = (probs.clone() - mean_probs.clone()).powi_scalar(2).mean_dim(0);
+
+    let predictions = probs.clone().argmax(2).reshape([num_members, batch_size]);
+    let votes = predictions.one_hot::<3>(num_classes).float().mean_dim(0);
+    let disagreement = votes.clone().max_dim(2).neg().add_scalar(1.0);
+
+    let predictive_entropy = entropy(mean_probs.clone());
+    let expected_entropy = entropy(probs).mean_dim(0);
+    let mutual_information = (predictive_entropy - expected_entropy).clamp_min(0.0);
+
+    let probabilities = match method {
+        EnsembleMethod::Mean => mean_probs,
+        EnsembleMethod::Vote => votes,
+    };
+    EnsembleOutput {
+        probabilities: probabilities.reshape([batch_size, num_classes]),
+        variance: variance.reshape([batch_size, num_classes]),
+        disagreement: disagreement.reshape([batch_size]),
+        mutual_information: mutual_information.reshape([batch_size]),
+    }
+}
+
+/// Entropy over the last dimension, keeping it with size 1
+fn entropy<B: Backend>(probs: Tensor<B, 3>) -> Tensor<B, 3> {
+    (probs.clone() * probs.clamp_min(1e-12).log()).sum_dim(2).neg()
+}
+
+#[cfg(test)]
+mod tests {
+    use burn_ndarray::NdArray;
+
+    use super::*;
+
+    fn probs(rows: &[[f32; 2]]) -> Tensor<NdArray, 2> {
+        let flat: Vec<f32> = rows.iter().flatten().copied().collect();
+        Tensor::<NdArray, 1>::from_floats(flat.as_slice(), &Default::default()).reshape([rows.len(), 2])
+    }
+
+    fn values<const D: usize>(tensor: Tensor<NdArray, D>) -> Vec<f32> {
+        tensor.into_data().to_vec::<f32>().unwrap()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    /// Three members on two images: split 2-1 on the first, unanimous on the second
+    fn members() -> Vec<Tensor<NdArray, 2>> {
+        vec![probs(&[[0.9, 0.1], [0.2, 0.8]]), probs(&[[0.6, 0.4], [0.4, 0.6]]), probs(&[[0.3, 0.7], [0.0, 1.0]])]
+    }
+
+    #[test]
+    fn mean_averages_the_probabilities() {
+        let output = combine(members(), EnsembleMethod::Mean);
+        assert_close(&values(output.probabilities), &[0.6, 0.4, 0.2, 0.8]);
+        // Member deviations from the mean: ±0.3, 0, ∓0.3 and 0, ±0.2, ∓0.2
+        assert_close(&values(output.variance), &[0.06, 0.06, 0.08 / 3.0, 0.08 / 3.0]);
+    }
+
+    #[test]
+    fn vote_reports_the_share_of_members_per_class() {
+        let output = combine(members(), EnsembleMethod::Vote);
+        assert_close(&values(output.probabilities), &[2.0 / 3.0, 1.0 / 3.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn disagreement_is_the_minority_share() {
+        let output = combine(members(), EnsembleMethod::Mean);
+        assert_close(&values(output.disagreement), &[1.0 / 3.0, 0.0]);
+    }
+
+    #[test]
+    fn identical_members_carry_no_information() {
+        let member = probs(&[[0.7, 0.3]]);
+        let output = combine(vec![member.clone(), member], EnsembleMethod::Mean);
+        assert_close(&values(output.variance), &[0.0, 0.0]);
+        assert_close(&values(output.mutual_information), &[0.0]);
+        assert_close(&values(output.disagreement), &[0.0]);
+    }
+
+    #[test]
+    fn method_names_parse() {
+        assert_eq!("mean".parse::<EnsembleMethod>().unwrap(), EnsembleMethod::Mean);
+        assert_eq!("vote".parse::<EnsembleMethod>().unwrap(), EnsembleMethod::Vote);
+        assert!("median".parse::<EnsembleMethod>().is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! Offline evaluation of a trained checkpoint, or an ensemble of checkpoints, on the validation split
+
+use std::{path::Path, time::Instant};
+
+use anyhow::{anyhow, bail};
+use burn::{
+    data::dataloader::batcher::Batcher,
+    tensor::{activation::softmax, backend::Backend, Bool, ElementConversion, Int, Tensor},
+};
+use crate::{
+    classifier::{load_inference_model, InferenceModel},
+    config::ModelConfig,
+    data::{MalariaBatch, MalariaBatcher, MalariaDataset},
+    ensemble::{combine, EnsembleMethod},
+    tta::{average_views, TtaMode},
+};
+
+pub struct MalariaEvaluator<B: Backend> {
+    config: ModelConfig,
+    device: B::Device,
+}
+
+impl<B: Backend> MalariaEvaluator<B> {
+    pub fn new(config: ModelConfig, device: B::Device) -> Self {
+        Self { config, device }
+    }
+
+    /// Evaluates a single checkpoint with and without TTA, or the ensemble of several checkpoints,
+    /// on the images of `data` (the validation split of `./data` by default)
+    pub fn run(&self, model_paths: &[String], tta: TtaMode, method: EnsembleMethod, data: Option<&Path>) -> anyhow::Result<()> {
+        match model_paths {
+            [] => bail!("Aucun modèle à évaluer"),
+            [model_path] => self.run_single(model_path, tta, data),
+            _ => {
+                // Cross-validation folds were trained on most of the validation split of ./data
+                let data = data.ok_or_else(|| {
+                    anyhow!("L'évaluation d'un ensemble demande --data <dossier> d'images jamais vues à l'entraînement")
+                })?;
+                self.run_ensemble(model_paths, method, data)
+            }
+        }
+    }
+
+    fn run_single(&self, model_path: &str, tta: TtaMode, data: Option<&Path>) -> anyhow::Result<()> {
+        println!("🔎 Évaluation du modèle: {}", model_path);
+
+        let (config, model) = self.load_member(model_path)?;
+        let valid_dataset = evaluation_dataset(&config, data)?;
+
+        let batcher = MalariaBatcher::<B>::new(valid_dataset.preprocessor.clone());
+        let mut correct_plain = 0;
+        let mut correct_tta = 0;
+        let total = valid_dataset.len();
+
+        for start in (0..total).step_by(config.batch_size) {
+            let end = (start + config.batch_size).min(total);
+            let items = (start..end).filter_map(|i| valid_dataset.get(i)).collect();
+            let batch = batcher.batch(items, &self.device);
+
+            let probs = softmax(model.forward(batch.images.clone()), 1);
+            correct_plain += count_correct(probs, batch.labels.clone());
+
+            let (views, num_views) = tta.augment(batch.images);
+            let probs = average_views(softmax(model.forward(views), 1), num_views);
+            correct_tta += count_correct(probs, batch.labels);
+        }
+
+        let accuracy_plain = correct_plain as f64 / total.max(1) as f64;
+        let accuracy_tta = correct_tta as f64 / total.max(1) as f64;
+
+        println!("📊 Résultats sur {} images d'évaluation:", total);
+        println!("   - Précision sans TTA: {:.2}%", accuracy_plain * 100.0);
+        println!("   - Précision avec TTA ({:?}): {:.2}%", tta, accuracy_tta * 100.0);
+        println!("   - Gain: {:+.2} points", (accuracy_tta - accuracy_plain) * 100.0);
+
+        Ok(())
+    }
+
+    /// Compares every member with their combination, and reports how well disagreement flags errors
+    fn run_ensemble(&self, model_paths: &[String], method: EnsembleMethod, data: &Path) -> anyhow::Result<()> {
+        println!("🔎 Évaluation d'un ensemble de {} modèles ({:?}) sur {}", model_paths.len(), method, data.display());
+
+        let mut members = Vec::with_capacity(model_paths.len());
+        let mut labels = None;
+        for model_path in model_paths {
+            let (config, model) = self.load_member(model_path)?;
+            // Each member sees the held-out images at its own input size
+            let valid_dataset = evaluation_dataset(&config, Some(data))?;
+            let batcher = MalariaBatcher::<B>::new(valid_dataset.preprocessor.clone());
+            let (probs, member_labels): (Vec<Tensor<B, 2>>, Vec<_>) =
+                batches(&batcher, &valid_dataset, valid_dataset.len(), config.batch_size, &self.device)
+                    .map(|batch| (softmax(model.forward(batch.images), 1), batch.labels))
+                    .unzip();
+            if probs.is_empty() {
+                bail!("Aucune image de validation");
+            }
+            let member_labels = Tensor::cat(member_labels, 0);
+            match &labels {
+                None => labels = Some(member_labels),
+                Some(labels)
+                    if labels.dims() != member_labels.dims()
+                        || !labels.to_data().iter::<i64>().eq(member_labels.to_data().iter::<i64>()) =>
+                {
+                    bail!("{} n'a pas été évalué sur les mêmes images", model_path)
+                }
+                Some(_) => {}
+            }
+            members.push(Tensor::cat(probs, 0));
+        }
+        let labels = labels.expect("at least two members");
+        let total = labels.dims()[0];
+
+        let member_accuracies: Vec<f64> = members
+            .iter()
+            .map(|probs| count_correct(probs.clone(), labels.clone()) as f64 / total as f64)
+            .collect();
+        let ensemble = combine(members, method);
+        let correct = ensemble.probabilities.argmax(1).reshape([total]).equal(labels);
+        let accuracy = correct.clone().int().sum().into_scalar().elem::<i64>() as f64 / total as f64;
+
+        // Accuracy on the images all members agree on, and on the others
+        let unanimous = ensemble.disagreement.clone().lower_equal_elem(0.0);
+        let count = |mask: Tensor<B, 1, Bool>| mask.int().sum().into_scalar().elem::<i64>() as usize;
+        let unanimous_total = count(unanimous.clone());
+        let unanimous_correct = count(unanimous.clone().bool_and(correct.clone()));
+        let split_total = total - unanimous_total;
+        let split_correct = count(unanimous.bool_not().bool_and(correct));
+        let rate = |correct: usize, total: usize| correct as f64 / total.max(1) as f64 * 100.0;
+
+        println!("📊 Résultats sur {} images d'évaluation:", total);
+        for (model_path, accuracy) in model_paths.iter().zip(&member_accuracies) {
+            println!("   - {}: {:.2}%", model_path, accuracy * 100.0);
+        }
+        let best = member_accuracies.iter().copied().fold(0.0, f64::max);
+        println!("   - Ensemble ({:?}): {:.2}%", method, accuracy * 100.0);
+        println!("   - Gain sur le meilleur modèle: {:+.2} points", (accuracy - best) * 100.0);
+        println!(
+            "   - Désaccord moyen: {:.2}%",
+            ensemble.disagreement.mean().into_scalar().elem::<f64>() * 100.0
+        );
+        println!(
+            "   - Précision si unanimes: {:.2}% ({} images), en désaccord: {:.2}% ({} images)",
+            rate(unanimous_correct, unanimous_total),
+            unanimous_total,
+            rate(split_correct, split_total),
+            split_total
+        );
+
+        Ok(())
+    }
+
+    /// Loads a checkpoint with the config saved next to it (the command-line config otherwise);
+    /// quantized checkpoints run on the int8 engine
+    fn load_member(&self, model_path: &str) -> anyhow::Result<(ModelConfig, InferenceModel<B>)> {
+        let model_path = Path::new(model_path).with_extension("");
+        let bundle = model_path.with_extension("json");
+        let config = if bundle.exists() { ModelConfig::load(&bundle)? } else { self.config.clone() };
+        let model = load_inference_model(&model_path, &config, &self.device)?;
+        Ok((config, model))
+    }
+}
+
+/// Every image of `data`, or the validation split of `./data` (same seed and ratio as training)
+fn evaluation_dataset(config: &ModelConfig, data: Option<&Path>) -> anyhow::Result<MalariaDataset> {
+    match data {
+        Some(data) => MalariaDataset::new(data, config.preprocessor()?, false),
+        None => Ok(MalariaDataset::new("data", config.preprocessor()?, false)?.split(0.8).1),
+    }
+}
+
+fn count_correct<B: Backend>(probs: Tensor<B, 2>, labels: Tensor<B, 1, Int>) -> usize {
+    let batch_size = labels.dims()[0];
+    let predictions = probs.argmax(1).reshape([batch_size]);
+    predictions.equal(labels).int().sum().into_scalar().elem::<i64>() as usize
+}
+
+/// Batches of the first `count` items of `dataset`, loaded one at a time as they are consumed
+pub fn batches<'a, B: Backend>(
+    batcher: &'a MalariaBatcher<B>,
+    dataset: &'a MalariaDataset,
+    count: usize,
+    batch_size: usize,
+    device: &'a B::Device,
+) -> impl Iterator<Item = MalariaBatch<B>> + 'a {
+    let count = count.min(dataset.len());
+    let batch_size = batch_size.max(1);
+    (0..count).step_by(batch_size).map(move |start| {
+        let end = (start + batch_size).min(count);
+        batcher.batch((start..end).filter_map(|i| dataset.get(i)).collect(), device)
+    })
+}
+
+/// Accuracy and forward time (in seconds, after one warm-up pass) of each model over `batches`.
+/// Every model sees each batch as it is loaded; only the forward passes are timed.
+pub fn measure<B: Backend>(models: &[&InferenceModel<B>], batches: impl Iterator<Item = MalariaBatch<B>>) -> Vec<(f64, f64)> {
+    let mut correct = vec![0; models.len()];
+    let mut elapsed = vec![0.0; models.len()];
+    let mut total = 0;
+    for (i, batch) in batches.enumerate() {
+        for (k, model) in models.iter().enumerate() {
+            if i == 0 {
+                let _ = model.forward(batch.images.clone()).into_data();
+            }
+            let start = Instant::now();
+            let logits = model.forward(batch.images.clone()).into_data();
+            elapsed[k] += start.elapsed().as_secs_f64();
+            correct[k] += count_correct(Tensor::from_data(logits, &batch.labels.device()), batch.labels.clone());
+        }
+        total += batch.labels.dims()[0];
+    }
+    correct
+        .into_iter()
+        .zip(elapsed)
+        .map(|(correct, elapsed)| (correct as f64 / total.max(1) as f64, elapsed))
+        .collect()
+}
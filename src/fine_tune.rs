@@ -0,0 +1,293 @@
+//! Fine-tuning from pretrained weights: loading and per-group optimizer steps
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use burn::{
+    module::{Module, ModuleVisitor, Param},
+    optim::{GradientsParams, Optimizer},
+    record::{FullPrecisionSettings, Recorder},
+    tensor::{
+        backend::{AutodiffBackend, Backend},
+        Tensor,
+    },
+};
+use burn_import::pytorch::{LoadArgs, PyTorchFileRecorder};
+use regex::Regex;
+use crate::{
+    classifier::load_record,
+    config::{FineTuneConfig, ModelConfig},
+    malaria_cnn::{MalariaCNN, MalariaCNNRecord},
+};
+
+/// Loads the pretrained weights into a freshly initialized `model` built from `config`.
+///
+/// The output layer stays freshly initialized when the checkpoint has another number of classes.
+pub fn load_pretrained<B: Backend>(
+    model: MalariaCNN<B>,
+    config: &ModelConfig,
+    fine_tune: &FineTuneConfig,
+    device: &B::Device,
+) -> Result<MalariaCNN<B>> {
+    let path = Path::new(&fine_tune.pretrained_path);
+    let record: MalariaCNNRecord<B> = match path.extension().and_then(|e| e.to_str()) {
+        Some("pt" | "pth") => {
+            let mut args = LoadArgs::new(path.to_path_buf());
+            for (pattern, replacement) in &fine_tune.key_remap {
+                let regex = Regex::new(pattern).map_err(|e| anyhow!("key_remap invalide {}: {}", pattern, e))?;
+                args.key_remap.push((regex, replacement.clone()));
+            }
+            args.top_level_key = fine_tune.top_level_key.clone();
+            PyTorchFileRecorder::<FullPrecisionSettings>::new()
+                .load(args, device)
+                .map_err(|e| anyhow!("Lecture des poids PyTorch {}: {}", path.display(), e))?
+        }
+        _ => {
+            check_bundle_architecture(path, config)?;
+            load_record(&path.with_extension(""), config, device)?
+        }
+    };
+
+    let expected = ParamShapes::of(&model);
+    let (model, reinit) = model.load_pretrained(record);
+    let shapes = ParamShapes::of(&model);
+    if reinit {
+        println!("🔁 Couche de sortie réinitialisée ({} classes)", config.num_classes);
+    }
+    if let Some((index, (found, wanted))) = shapes.0.iter().zip(&expected.0).enumerate().find(|(_, (a, b))| a != b) {
+        bail!(
+            "Poids pré-entraînés incompatibles: paramètre {} de forme {:?}, attendu {:?}",
+            index,
+            found,
+            wanted
+        );
+    }
+    Ok(model)
+}
+
+/// Our checkpoints carry their config next to the weights; a different backbone cannot be loaded,
+/// a different normalization is reported
+fn check_bundle_architecture(path: &Path, config: &ModelConfig) -> Result<()> {
+    let bundle = path.with_extension("json");
+    if !bundle.exists() {
+        return Ok(());
+    }
+    let pretrained = ModelConfig::load(&bundle)?;
+    if pretrained.architecture != config.architecture
+        || pretrained.conv_blocks != config.conv_blocks
+        || pretrained.fc_units != config.fc_units
+        || pretrained.image_channels != config.image_channels
+    {
+        bail!(
+            "Le checkpoint {} a une autre architecture ({:?}) que la configuration ({:?})",
+            path.display(),
+            pretrained.architecture,
+            config.architecture
+        );
+    }
+    if pretrained.preprocessing.normalization != config.preprocessing.normalization {
+        println!(
+            "⚠️  {} a été entraîné avec la normalisation {:?}, la configuration utilise {:?}",
+            path.display(),
+            pretrained.preprocessing.normalization,
+            config.preprocessing.normalization
+        );
+    }
+    Ok(())
+}
+
+/// Shapes of every float parameter, in visiting order
+struct ParamShapes(Vec<Vec<usize>>);
+
+impl ParamShapes {
+    fn of<B: Backend, M: Module<B>>(module: &M) -> Self {
+        let mut shapes = Self(Vec::new());
+        module.visit(&mut shapes);
+        shapes
+    }
+}
+
+impl<B: Backend> ModuleVisitor<B> for ParamShapes {
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        self.0.push(param.val().dims().to_vec());
+    }
+}
+
+/// Moves the gradients of the visited parameters into another set
+struct GradientsSplit<'a> {
+    from: &'a mut GradientsParams,
+    to: GradientsParams,
+}
+
+impl<B: AutodiffBackend> ModuleVisitor<B> for GradientsSplit<'_> {
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        if let Some(grad) = self.from.remove::<B::InnerBackend, D>(param.id) {
+            self.to.register::<B::InnerBackend, D>(param.id, grad);
+        }
+    }
+}
+
+/// Steps the backbone and the classifier head as two parameter groups: the backbone learning
+/// rate is scaled, and the backbone weights are left untouched during the first `frozen_steps`
+/// steps. BatchNorm running statistics are updated by the forward pass, not by the optimizer,
+/// so they keep adapting while the weights are frozen.
+#[derive(Clone)]
+pub struct ParamGroupOptimizer<O> {
+    inner: O,
+    backbone_lr_scale: f64,
+    frozen_steps: usize,
+    /// Optimizer steps taken so far, saved with the checkpoints
+    step: usize,
+}
+
+impl<O> ParamGroupOptimizer<O> {
+    /// `steps_per_epoch` converts the frozen epochs into optimizer steps
+    pub fn new(inner: O, fine_tune: Option<&FineTuneConfig>, steps_per_epoch: usize) -> Self {
+        Self {
+            inner,
+            backbone_lr_scale: fine_tune.map_or(1.0, |f| f.backbone_lr_scale),
+            frozen_steps: fine_tune.map_or(0, |f| f.freeze_backbone_epochs * steps_per_epoch),
+            step: 0,
+        }
+    }
+}
+
+impl<B, O> Optimizer<MalariaCNN<B>, B> for ParamGroupOptimizer<O>
+where
+    B: AutodiffBackend,
+    O: Optimizer<MalariaCNN<B>, B>,
+{
+    type Record = (O::Record, usize);
+
+    fn step(&mut self, lr: f64, module: MalariaCNN<B>, mut grads: GradientsParams) -> MalariaCNN<B> {
+        self.step += 1;
+        let frozen = self.step <= self.frozen_steps;
+        if !frozen && self.backbone_lr_scale == 1.0 {
+            return self.inner.step(lr, module, grads);
+        }
+
+        let mut split = GradientsSplit { from: &mut grads, to: GradientsParams::new() };
+        module.backbone().visit(&mut split);
+        let backbone_grads = split.to;
+        let module = if frozen {
+            module
+        } else {
+            self.inner.step(lr * self.backbone_lr_scale, module, backbone_grads)
+        };
+        self.inner.step(lr, module, grads)
+    }
+
+    fn to_record(&self) -> Self::Record {
+        (self.inner.to_record(), self.step)
+    }
+
+    fn load_record(self, (record, step): Self::Record) -> Self {
+        Self { inner: self.inner.load_record(record), step, ..self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::{
+        backend::Autodiff,
+        optim::SgdConfig,
+        tensor::Distribution,
+    };
+    use burn_ndarray::NdArray;
+
+    use super::*;
+    use crate::{
+        architecture::{Activation, ConvBlockConfig, Normalization},
+        classifier::build_model,
+    };
+
+    type B = Autodiff<NdArray>;
+
+    /// Without BatchNorm, whose running statistics change in the forward pass and are shared by clones,
+    /// nor ReLU, whose units can all start dead and leave the backbone without gradient
+    fn model() -> MalariaCNN<B> {
+        let block = ConvBlockConfig { norm: Normalization::None, activation: Activation::Silu, ..ConvBlockConfig::new(4) };
+        let config = ModelConfig {
+            image_height: 8,
+            image_width: 8,
+            conv_blocks: vec![block],
+            fc_units: vec![],
+            dropout_rate: 0.0,
+            ..ModelConfig::default()
+        };
+        build_model(&config, &Default::default())
+    }
+
+    fn fine_tune(freeze_backbone_epochs: usize, backbone_lr_scale: f64) -> FineTuneConfig {
+        FineTuneConfig {
+            pretrained_path: String::new(),
+            key_remap: Vec::new(),
+            top_level_key: None,
+            freeze_backbone_epochs,
+            backbone_lr_scale,
+        }
+    }
+
+    fn grads(model: &MalariaCNN<B>, input: Tensor<B, 4>) -> GradientsParams {
+        let loss = model.forward(input).powi_scalar(2).mean();
+        GradientsParams::from_grads(loss.backward(), model)
+    }
+
+    fn input() -> Tensor<B, 4> {
+        Tensor::<B, 4>::random([2, 3, 8, 8], Distribution::Default, &Default::default())
+    }
+
+    /// Values of every float parameter, in visiting order
+    struct Values(Vec<Vec<f32>>);
+
+    impl<B: Backend> ModuleVisitor<B> for Values {
+        fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+            self.0.push(param.val().into_data().to_vec::<f32>().unwrap());
+        }
+    }
+
+    fn values<M: Module<B>>(module: &M) -> Vec<f32> {
+        let mut values = Values(Vec::new());
+        module.visit(&mut values);
+        values.0.concat()
+    }
+
+    #[test]
+    fn backbone_stays_frozen_for_the_frozen_steps() {
+        let mut optim = ParamGroupOptimizer::new(SgdConfig::new().init(), Some(&fine_tune(1, 1.0)), 2);
+        let mut model = model();
+        for step in 1..=3 {
+            let (backbone, all) = (values(model.backbone()), values(&model));
+            let grads = grads(&model, input());
+            model = optim.step(0.1, model, grads);
+            assert_ne!(values(&model), all, "head not updated at step {}", step);
+            // One frozen epoch of 2 steps
+            assert_eq!(values(model.backbone()) == backbone, step <= 2, "backbone frozen state wrong at step {}", step);
+        }
+        assert_eq!(Optimizer::<MalariaCNN<B>, B>::to_record(&optim).1, 3);
+    }
+
+    #[test]
+    fn backbone_learning_rate_is_scaled() {
+        let (start, input) = (model(), input());
+        let backbone = values(start.backbone());
+
+        let mut plain = ParamGroupOptimizer::new(SgdConfig::new().init(), None, 1);
+        let full = plain.step(0.1, start.clone(), grads(&start, input.clone()));
+        let mut grouped = ParamGroupOptimizer::new(SgdConfig::new().init(), Some(&fine_tune(0, 0.5)), 1);
+        let scaled = grouped.step(0.1, start.clone(), grads(&start, input));
+
+        let delta = |after: Vec<f32>, before: &[f32]| -> Vec<f32> { after.iter().zip(before).map(|(a, b)| a - b).collect() };
+        let full_delta = delta(values(full.backbone()), &backbone);
+        let scaled_delta = delta(values(scaled.backbone()), &backbone);
+        assert!(full_delta.iter().any(|d| d.abs() > 1e-6));
+        for (full, scaled) in full_delta.iter().zip(&scaled_delta) {
+            assert!((scaled - 0.5 * full).abs() < 1e-5, "{} is not half of {}", scaled, full);
+        }
+        // The head moves by the full step in both runs
+        let head = |model: &MalariaCNN<B>| values(model)[backbone.len()..].to_vec();
+        for (full, scaled) in head(&full).iter().zip(head(&scaled)) {
+            assert!((full - scaled).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,596 @@
+//! Int8 inference of the custom conv stack, for CPUs without a GPU
+//!
+//! Conv and linear layers multiply 8-bit activations, quantized over ranges calibrated on
+//! training images, by 8-bit weights with 32-bit accumulation. BatchNorm and biases are folded
+//! into the rescaling of the accumulators; GroupNorm, activations and pooling run in f32 between
+//! the quantized layers. Feature maps are laid out channel-last so that conv patches are contiguous.
+
+use anyhow::{bail, ensure, Result};
+use burn::{
+    nn::{conv::Conv2d, Linear, PaddingConfig2d},
+    tensor::{backend::Backend, Tensor, TensorData},
+};
+use rayon::prelude::*;
+
+use crate::{
+    architecture::{Activation, Backbone, ConvBlock, Norm, Pooling},
+    malaria_cnn::MalariaCNN,
+};
+
+/// `[min, max]` of the input of each quantized layer, in forward order: the conv blocks, then the
+/// classifier layers
+pub fn calibrate<B: Backend>(model: &MalariaCNN<B>, batches: impl Iterator<Item = Tensor<B, 4>>) -> Result<Vec<[f32; 2]>> {
+    let Backbone::Custom(stack) = &model.features else {
+        bail!("Int8 inference only covers the custom conv stack");
+    };
+    let mut ranges: Vec<[f32; 2]> = Vec::new();
+    for images in batches {
+        let mut inputs = Vec::new();
+        let x = stack.blocks().iter().fold(images, |x, block| {
+            inputs.push(x.clone().flatten::<1>(0, 3));
+            block.forward(x)
+        });
+        let x = model.adaptive_pool.forward(x).flatten::<2>(1, 3);
+        let x = model.hidden.iter().fold(x, |x, layer| {
+            inputs.push(x.clone().flatten::<1>(0, 1));
+            model.relu.forward(layer.forward(x))
+        });
+        inputs.push(x.flatten::<1>(0, 1));
+
+        for (layer, input) in inputs.into_iter().enumerate() {
+            let values = input.into_data().convert::<f32>();
+            let values = values.as_slice::<f32>().map_err(|e| anyhow::anyhow!("{:?}", e))?;
+            let (min, max) = values.iter().fold((f32::MAX, f32::MIN), |(min, max), &v| (min.min(v), max.max(v)));
+            match ranges.get_mut(layer) {
+                Some(range) => *range = [range[0].min(min), range[1].max(max)],
+                None => ranges.push([min, max]),
+            }
+        }
+    }
+    Ok(ranges)
+}
+
+/// Int8 copy of a `MalariaCNN` with the custom conv stack
+#[derive(Debug)]
+pub struct Int8Model {
+    blocks: Vec<Int8Block>,
+    /// Side of the adaptive average pooling before the classifier
+    pooled: usize,
+    hidden: Vec<Int8Linear>,
+    output: Int8Linear,
+}
+
+impl Int8Model {
+    /// Quantizes the weights of `model` (per tensor, symmetric) and its activations over `ranges`,
+    /// as returned by [`calibrate`]
+    pub fn new<B: Backend>(model: &MalariaCNN<B>, ranges: &[[f32; 2]]) -> Result<Self> {
+        let Backbone::Custom(stack) = &model.features else {
+            bail!("Int8 inference only covers the custom conv stack");
+        };
+        let layers = stack.blocks().len() + model.hidden.len() + 1;
+        ensure!(ranges.len() == layers, "{} activation ranges for {} quantized layers", ranges.len(), layers);
+
+        let (block_ranges, head_ranges) = ranges.split_at(stack.blocks().len());
+        let blocks = stack
+            .blocks()
+            .iter()
+            .zip(block_ranges)
+            .map(|(block, &range)| Int8Block::new(block, ActivationScale::new(range)))
+            .collect::<Result<_>>()?;
+        let mut head = model
+            .hidden
+            .iter()
+            .chain([&model.output])
+            .zip(head_ranges)
+            .map(|(layer, &range)| Int8Linear::new(layer, ActivationScale::new(range)))
+            .collect::<Result<Vec<_>>>()?;
+        let output = head.pop().expect("the output layer");
+        let [pooled, _] = model.adaptive_pool.output_size;
+        Ok(Self { blocks, pooled, hidden: head, output })
+    }
+
+    /// Logits `[batch, classes]` of `[batch, channels, height, width]` images
+    pub fn forward<B: Backend>(&self, x: Tensor<B, 4>) -> Tensor<B, 2> {
+        let device = x.device();
+        let [batch, channels, height, width] = x.dims();
+        let pixels = x.into_data().convert::<f32>().to_vec::<f32>().expect("f32 pixels");
+        let logits: Vec<f32> = pixels
+            .chunks_exact(channels * height * width)
+            .flat_map(|image| self.forward_image(image, channels, height, width))
+            .collect();
+        let classes = logits.len() / batch.max(1);
+        Tensor::from_data(TensorData::new(logits, [batch, classes]), &device)
+    }
+
+    fn forward_image(&self, chw: &[f32], channels: usize, height: usize, width: usize) -> Vec<f32> {
+        let mut map = FeatureMap::from_chw(chw, channels, height, width);
+        for block in &self.blocks {
+            map = block.forward(&map);
+        }
+        let mut x = map.adaptive_avg_pool(self.pooled);
+        for layer in &self.hidden {
+            x = layer.forward(&x);
+            x.iter_mut().for_each(|v| *v = v.max(0.0));
+        }
+        self.output.forward(&x)
+    }
+}
+
+/// Channel-last f32 feature map of one image
+struct FeatureMap {
+    values: Vec<f32>,
+    height: usize,
+    width: usize,
+    channels: usize,
+}
+
+impl FeatureMap {
+    fn from_chw(chw: &[f32], channels: usize, height: usize, width: usize) -> Self {
+        let frame = height * width;
+        let values = (0..frame).flat_map(|pixel| (0..channels).map(move |c| chw[c * frame + pixel])).collect();
+        Self { values, height, width, channels }
+    }
+
+    fn pool(self, pooling: Pooling) -> Self {
+        let (size, max) = match pooling {
+            Pooling::None => return self,
+            Pooling::Max { size } => (size, true),
+            Pooling::Avg { size } => (size, false),
+        };
+        let (height, width, channels) = (self.height / size, self.width / size, self.channels);
+        let mut values = vec![if max { f32::MIN } else { 0.0 }; height * width * channels];
+        for (i, out) in values.chunks_exact_mut(channels).enumerate() {
+            let (y, x) = (i / width, i % width);
+            for dy in 0..size {
+                for dx in 0..size {
+                    let start = ((y * size + dy) * self.width + x * size + dx) * channels;
+                    for (o, &v) in out.iter_mut().zip(&self.values[start..start + channels]) {
+                        *o = if max { o.max(v) } else { *o + v };
+                    }
+                }
+            }
+            if !max {
+                out.iter_mut().for_each(|o| *o /= (size * size) as f32);
+            }
+        }
+        Self { values, height, width, channels }
+    }
+
+    /// Average pooling to `size` x `size` cells, flattened channel by channel like the f32 model
+    fn adaptive_avg_pool(&self, size: usize) -> Vec<f32> {
+        // Same cell bounds as Burn's adaptive pooling
+        let start = |i: usize, input: usize| (i * input) / size;
+        let end = |i: usize, input: usize| ((i + 1) * input).div_ceil(size).min(input);
+        let mut pooled = vec![0.0; self.channels * size * size];
+        for oy in 0..size {
+            for ox in 0..size {
+                let (rows, columns) = (start(oy, self.height)..end(oy, self.height), start(ox, self.width)..end(ox, self.width));
+                let count = (rows.len() * columns.len()) as f32;
+                for y in rows {
+                    for x in columns.clone() {
+                        let pixel = &self.values[(y * self.width + x) * self.channels..][..self.channels];
+                        for (c, &v) in pixel.iter().enumerate() {
+                            pooled[(c * size + oy) * size + ox] += v / count;
+                        }
+                    }
+                }
+            }
+        }
+        pooled
+    }
+}
+
+/// Asymmetric 8-bit mapping of a calibrated `[min, max]` range, widened to contain 0 so that the
+/// conv padding is exact
+#[derive(Debug, Clone, Copy)]
+struct ActivationScale {
+    scale: f32,
+    zero_point: i32,
+}
+
+impl ActivationScale {
+    fn new([min, max]: [f32; 2]) -> Self {
+        let (min, max) = (min.min(0.0), max.max(0.0));
+        let scale = ((max - min) / 255.0).max(f32::EPSILON);
+        Self { scale, zero_point: (-min / scale).round() as i32 }
+    }
+
+    /// Values in 0..=255, widened to i16 for the dot products
+    fn quantize(&self, values: &[f32]) -> Vec<i16> {
+        let (inverse, offset) = (1.0 / self.scale, self.zero_point as f32 + 0.5);
+        // Non-negative once clamped, so the truncation rounds to nearest
+        values.iter().map(|&v| (v * inverse + offset).clamp(0.0, 255.0) as i16).collect()
+    }
+}
+
+/// Symmetric per-tensor int8 weights, widened to i16 and packed so that each pair of inputs is
+/// multiplied with [`GROUP`] outputs at once
+#[derive(Debug)]
+struct Int8Weights {
+    /// `[inputs / 2][groups · GROUP][2]`, the outputs zero-padded to whole groups
+    packed: Vec<i16>,
+    /// Even, zero-padded
+    inputs: usize,
+    outputs: usize,
+    /// `y = (Σ q_x·q_w - zero_point·Σ q_w) · multiplier + offset`, per output
+    multiplier: Vec<f32>,
+    offset: Vec<f32>,
+}
+
+/// Outputs computed together: eight i32 lanes of an AVX2 register
+const GROUP: usize = 8;
+
+impl Int8Weights {
+    /// `rows` of f32 weights (one per output), `scale` and `shift` applied per output after the
+    /// product (folded bias and BatchNorm)
+    fn new(rows: &[f32], outputs: usize, input: ActivationScale, scale: &[f32], shift: &[f32]) -> Self {
+        // Same rounding as the int8 bundles (per-tensor symmetric), so their weights are recovered exactly
+        let max = rows.iter().fold(0.0f32, |max, w| max.max(w.abs()));
+        let weight_scale = (max / 127.0).max(f32::EPSILON);
+        let quantized: Vec<i16> = rows.iter().map(|w| (w / weight_scale).round().clamp(-127.0, 127.0) as i16).collect();
+
+        let unpadded = rows.len() / outputs;
+        let inputs = unpadded.next_multiple_of(2);
+        let width = outputs.next_multiple_of(GROUP);
+        let mut packed = vec![0; inputs * width];
+        for (o, row) in quantized.chunks_exact(unpadded).enumerate() {
+            for (i, &w) in row.iter().enumerate() {
+                packed[((i / 2) * width + o) * 2 + i % 2] = w;
+            }
+        }
+
+        let multiplier: Vec<f32> = scale.iter().map(|s| s * input.scale * weight_scale).collect();
+        let offset = quantized
+            .chunks_exact(unpadded)
+            .zip(&multiplier)
+            .zip(shift)
+            .map(|((row, m), shift)| shift - (input.zero_point * row.iter().map(|&w| w as i32).sum::<i32>()) as f32 * m)
+            .collect();
+        Self { packed, inputs, outputs, multiplier, offset }
+    }
+
+    /// Rescaled products of the quantized input `x` (`inputs` values) with every output, written
+    /// to `out`, on AVX2 when the CPU has it
+    fn apply(&self, x: &[i16], out: &mut [f32]) {
+        #[cfg(target_arch = "x86_64")]
+        if std::arch::is_x86_feature_detected!("avx2") {
+            // SAFETY: the CPU supports AVX2
+            return unsafe { self.apply_avx2(x, out) };
+        }
+        self.apply_portable(x, out)
+    }
+
+    fn apply_portable(&self, x: &[i16], out: &mut [f32]) {
+        let groups = self.outputs.div_ceil(GROUP);
+        for group in 0..groups {
+            let mut acc = [0i32; GROUP];
+            for (pair, weights) in x[..self.inputs].chunks_exact(2).zip(self.packed.chunks_exact(groups * GROUP * 2)) {
+                let (a, b) = (pair[0] as i32, pair[1] as i32);
+                for (acc, w) in acc.iter_mut().zip(weights[group * GROUP * 2..][..GROUP * 2].chunks_exact(2)) {
+                    *acc += a * w[0] as i32 + b * w[1] as i32;
+                }
+            }
+            self.rescale(group, &acc, out);
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx2")]
+    unsafe fn apply_avx2(&self, x: &[i16], out: &mut [f32]) {
+        // Up to four groups per pass over the inputs, their sums held in registers
+        let groups = self.outputs.div_ceil(GROUP);
+        let mut group = 0;
+        while group < groups {
+            group += match groups - group {
+                1 => self.groups_avx2::<1>(x, group, out),
+                2 => self.groups_avx2::<2>(x, group, out),
+                3 => self.groups_avx2::<3>(x, group, out),
+                _ => self.groups_avx2::<4>(x, group, out),
+            };
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx2")]
+    unsafe fn groups_avx2<const N: usize>(&self, x: &[i16], first: usize, out: &mut [f32]) -> usize {
+        use std::arch::x86_64::*;
+
+        let stride = self.outputs.div_ceil(GROUP) * GROUP * 2;
+        let mut acc = [_mm256_setzero_si256(); N];
+        for (pair, weights) in x[..self.inputs].chunks_exact(2).zip(self.packed.chunks_exact(stride)) {
+            // The input pair repeated in every lane; each i32 lane gets a·w0 + b·w1 (at most 2·255·127)
+            let pair = _mm256_set1_epi32((pair[0] as u16 as i32) | ((pair[1] as u16 as i32) << 16));
+            let weights = &weights[first * GROUP * 2..][..N * GROUP * 2];
+            for (acc, w) in acc.iter_mut().zip(weights.chunks_exact(GROUP * 2)) {
+                *acc = _mm256_add_epi32(*acc, _mm256_madd_epi16(pair, _mm256_loadu_si256(w.as_ptr().cast())));
+            }
+        }
+        for (i, acc) in acc.into_iter().enumerate() {
+            let mut sums = [0i32; GROUP];
+            _mm256_storeu_si256(sums.as_mut_ptr().cast(), acc);
+            self.rescale(first + i, &sums, out);
+        }
+        N
+    }
+
+    #[inline(always)]
+    fn rescale(&self, group: usize, sums: &[i32; GROUP], out: &mut [f32]) {
+        let start = group * GROUP;
+        for (o, &sum) in (start..self.outputs.min(start + GROUP)).zip(sums) {
+            out[o] = sum as f32 * self.multiplier[o] + self.offset[o];
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Int8Conv {
+    weights: Int8Weights,
+    out_channels: usize,
+    kernel: usize,
+    stride: usize,
+    padding: usize,
+}
+
+impl Int8Conv {
+    fn new<B: Backend>(conv: &Conv2d<B>, input: ActivationScale, norm: Option<(Vec<f32>, Vec<f32>)>) -> Result<Self> {
+        let [out_channels, in_channels, kernel, _] = conv.weight.dims();
+        ensure!(conv.groups == 1 && conv.dilation == [1, 1], "Int8 convolutions need groups = 1 and no dilation");
+        ensure!(conv.kernel_size[0] == conv.kernel_size[1] && conv.stride[0] == conv.stride[1], "Int8 convolutions need square kernels and strides");
+        let padding = match *conv.padding {
+            PaddingConfig2d::Valid => 0,
+            PaddingConfig2d::Explicit(height, width) if height == width => height,
+            ref other => bail!("Padding {:?} not supported by int8 convolutions", other),
+        };
+
+        // [out, in, ky, kx] -> [out, ky, kx, in], the order of the channel-last patches
+        let weights = read(conv.weight.val())?;
+        let area = kernel * kernel;
+        let rows: Vec<f32> = (0..out_channels)
+            .flat_map(|o| (0..area).flat_map(move |k| (0..in_channels).map(move |c| (o, c, k))))
+            .map(|(o, c, k)| weights[(o * in_channels + c) * area + k])
+            .collect();
+        let bias = match &conv.bias {
+            Some(bias) => read(bias.val())?,
+            None => vec![0.0; out_channels],
+        };
+        let (scale, shift) = match norm {
+            Some((scale, shift)) => {
+                let shift = bias.iter().zip(&scale).zip(shift).map(|((b, s), shift)| b * s + shift).collect();
+                (scale, shift)
+            }
+            None => (vec![1.0; out_channels], bias),
+        };
+        Ok(Self {
+            weights: Int8Weights::new(&rows, out_channels, input, &scale, &shift),
+            out_channels,
+            kernel,
+            stride: conv.stride[0],
+            padding,
+        })
+    }
+
+    /// Convolution of a map quantized with the input scale (`zero_point` fills the padding)
+    fn forward(&self, input: &[i16], zero_point: i16, height: usize, width: usize, channels: usize) -> FeatureMap {
+        let (kernel, stride, padding) = (self.kernel, self.stride, self.padding);
+        let out_height = (height + 2 * padding - kernel) / stride + 1;
+        let out_width = (width + 2 * padding - kernel) / stride + 1;
+        let mut values = vec![0.0; out_height * out_width * self.out_channels];
+
+        // One output row per task, each with its own patch buffer
+        values.par_chunks_mut(out_width * self.out_channels).enumerate().for_each(|(oy, row)| {
+            // Trailing padding up to the weight rows stays 0
+            let mut patch = vec![0; self.weights.inputs];
+            for (ox, out) in row.chunks_exact_mut(self.out_channels).enumerate() {
+                for ky in 0..kernel {
+                    for kx in 0..kernel {
+                        let target = &mut patch[(ky * kernel + kx) * channels..][..channels];
+                        let y = (oy * stride + ky).checked_sub(padding).filter(|&y| y < height);
+                        let x = (ox * stride + kx).checked_sub(padding).filter(|&x| x < width);
+                        match (y, x) {
+                            (Some(y), Some(x)) => target.copy_from_slice(&input[(y * width + x) * channels..][..channels]),
+                            _ => target.fill(zero_point),
+                        }
+                    }
+                }
+                self.weights.apply(&patch, out);
+            }
+        });
+        FeatureMap { values, height: out_height, width: out_width, channels: self.out_channels }
+    }
+}
+
+/// conv -> norm -> (+ shortcut) -> activation -> pooling, as [`ConvBlock`]
+#[derive(Debug)]
+struct Int8Block {
+    input: ActivationScale,
+    /// BatchNorm is folded into the conv
+    conv: Int8Conv,
+    group_norm: Option<GroupNormParams>,
+    /// `None` without shortcut, `Some(None)` for an identity shortcut
+    shortcut: Option<Option<Int8Conv>>,
+    activation: Activation,
+    pooling: Pooling,
+}
+
+impl Int8Block {
+    fn new<B: Backend>(block: &ConvBlock<B>, input: ActivationScale) -> Result<Self> {
+        let (batch_norm, group_norm) = match &block.norm {
+            Some(Norm::Batch(norm)) => {
+                let gamma = read(norm.gamma.val())?;
+                let beta = read(norm.beta.val())?;
+                let mean = read(norm.running_mean.value())?;
+                let var = read(norm.running_var.value())?;
+                let scale: Vec<f32> = gamma.iter().zip(&var).map(|(g, v)| g / (v + norm.epsilon as f32).sqrt()).collect();
+                let shift = beta.iter().zip(&mean).zip(&scale).map(|((b, m), s)| b - m * s).collect();
+                (Some((scale, shift)), None)
+            }
+            Some(Norm::Group(norm)) => (None, Some(GroupNormParams::new(norm)?)),
+            None => (None, None),
+        };
+        let shortcut = match (block.residual, &block.projection) {
+            (false, _) => None,
+            (true, None) => Some(None),
+            (true, Some(projection)) => Some(Some(Int8Conv::new(projection, input, None)?)),
+        };
+        Ok(Self {
+            input,
+            conv: Int8Conv::new(&block.conv, input, batch_norm)?,
+            group_norm,
+            shortcut,
+            activation: *block.activation,
+            pooling: *block.pooling,
+        })
+    }
+
+    fn forward(&self, x: &FeatureMap) -> FeatureMap {
+        let quantized = self.input.quantize(&x.values);
+        let zero_point = self.input.zero_point as i16;
+        let mut y = self.conv.forward(&quantized, zero_point, x.height, x.width, x.channels);
+        if let Some(norm) = &self.group_norm {
+            norm.forward(&mut y);
+        }
+        match &self.shortcut {
+            Some(Some(projection)) => {
+                let shortcut = projection.forward(&quantized, zero_point, x.height, x.width, x.channels);
+                y.values.iter_mut().zip(&shortcut.values).for_each(|(y, s)| *y += s);
+            }
+            Some(None) => y.values.iter_mut().zip(&x.values).for_each(|(y, s)| *y += s),
+            None => {}
+        }
+        activate(self.activation, &mut y.values);
+        y.pool(self.pooling)
+    }
+}
+
+#[derive(Debug)]
+struct GroupNormParams {
+    groups: usize,
+    epsilon: f32,
+    /// Per-channel affine transform, when the norm has one
+    gamma: Option<Vec<f32>>,
+    beta: Option<Vec<f32>>,
+}
+
+impl GroupNormParams {
+    fn new<B: Backend>(norm: &burn::nn::GroupNorm<B>) -> Result<Self> {
+        Ok(Self {
+            groups: norm.num_groups,
+            epsilon: norm.epsilon as f32,
+            gamma: norm.gamma.as_ref().map(|gamma| read(gamma.val())).transpose()?,
+            beta: norm.beta.as_ref().map(|beta| read(beta.val())).transpose()?,
+        })
+    }
+
+    fn forward(&self, map: &mut FeatureMap) {
+        let channels = map.channels;
+        let per_group = channels / self.groups;
+        let count = (map.height * map.width * per_group) as f32;
+        for group in 0..self.groups {
+            let range = group * per_group..(group + 1) * per_group;
+            let pixels = || map.values.chunks_exact(channels).flat_map(|pixel| &pixel[range.clone()]);
+            let mean = pixels().sum::<f32>() / count;
+            let var = pixels().map(|v| (v - mean).powi(2)).sum::<f32>() / count;
+            let inverse = 1.0 / (var + self.epsilon).sqrt();
+            for pixel in map.values.chunks_exact_mut(channels) {
+                for c in range.clone() {
+                    let gamma = self.gamma.as_ref().map_or(1.0, |gamma| gamma[c]);
+                    let beta = self.beta.as_ref().map_or(0.0, |beta| beta[c]);
+                    pixel[c] = (pixel[c] - mean) * inverse * gamma + beta;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Int8Linear {
+    input: ActivationScale,
+    weights: Int8Weights,
+    outputs: usize,
+}
+
+impl Int8Linear {
+    fn new<B: Backend>(layer: &Linear<B>, input: ActivationScale) -> Result<Self> {
+        // Burn stores [inputs, outputs]
+        let [inputs, outputs] = layer.weight.dims();
+        let weights = read(layer.weight.val())?;
+        let rows: Vec<f32> = (0..outputs).flat_map(|o| (0..inputs).map(move |i| (i, o))).map(|(i, o)| weights[i * outputs + o]).collect();
+        let bias = match &layer.bias {
+            Some(bias) => read(bias.val())?,
+            None => vec![0.0; outputs],
+        };
+        Ok(Self { input, weights: Int8Weights::new(&rows, outputs, input, &vec![1.0; outputs], &bias), outputs })
+    }
+
+    fn forward(&self, x: &[f32]) -> Vec<f32> {
+        let mut quantized = self.input.quantize(x);
+        quantized.resize(self.weights.inputs, 0);
+        let mut out = vec![0.0; self.outputs];
+        self.weights.apply(&quantized, &mut out);
+        out
+    }
+}
+
+fn activate(activation: Activation, values: &mut [f32]) {
+    match activation {
+        Activation::Relu => values.iter_mut().for_each(|v| *v = v.max(0.0)),
+        Activation::Gelu => values.iter_mut().for_each(|v| *v = 0.5 * *v * (1.0 + erf(*v / std::f32::consts::SQRT_2))),
+        Activation::Silu => values.iter_mut().for_each(|v| *v /= 1.0 + (-*v).exp()),
+    }
+}
+
+/// Abramowitz and Stegun 7.1.26, within 1.5e-7 of the error function
+fn erf(x: f32) -> f32 {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let polynomial = t * (0.254_829_6 + t * (-0.284_496_74 + t * (1.421_413_8 + t * (-1.453_152_1 + t * 1.061_405_4))));
+    (1.0 - polynomial * (-x * x).exp()).copysign(x)
+}
+
+fn read<B: Backend, const D: usize>(tensor: Tensor<B, D>) -> Result<Vec<f32>> {
+    tensor.dequantize().into_data().convert::<f32>().to_vec::<f32>().map_err(|e| anyhow::anyhow!("Failed to read weights: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::tensor::Distribution;
+    use burn_ndarray::NdArray;
+
+    use super::*;
+    use crate::architecture::{Architecture, ConvBlockConfig, Normalization};
+
+    type B = NdArray;
+
+    fn model(conv_blocks: &[ConvBlockConfig]) -> MalariaCNN<B> {
+        MalariaCNN::new(&Default::default(), 3, &Architecture::Custom, conv_blocks, &[16], 2, 0.0)
+    }
+
+    fn max_error(model: &MalariaCNN<B>) -> f32 {
+        let images = Tensor::<B, 4>::random([4, 3, 16, 16], Distribution::Uniform(0.0, 1.0), &Default::default());
+        let ranges = calibrate(model, [images.clone()].into_iter()).unwrap();
+        let int8 = Int8Model::new(model, &ranges).unwrap();
+        let expected = model.forward(images.clone());
+        let scale = expected.clone().abs().max().into_scalar();
+        (int8.forward(images) - expected).abs().max().into_scalar() / scale
+    }
+
+    #[test]
+    fn matches_the_f32_model() {
+        let plain = [ConvBlockConfig::new(8), ConvBlockConfig::new(16)];
+        assert!(max_error(&model(&plain)) < 0.05);
+
+        // Stride, identity and projected shortcuts, GroupNorm, no norm, average pooling, GELU and SiLU
+        let mut varied = [ConvBlockConfig::new(8), ConvBlockConfig::new(8), ConvBlockConfig::new(12)];
+        varied[0] = ConvBlockConfig { stride: 2, activation: Activation::Gelu, pooling: Pooling::None, ..varied[0].clone() };
+        varied[1] = ConvBlockConfig { residual: true, norm: Normalization::GroupNorm { groups: 2 }, pooling: Pooling::Avg { size: 2 }, ..varied[1].clone() };
+        varied[2] = ConvBlockConfig { residual: true, norm: Normalization::None, activation: Activation::Silu, kernel_size: 5, ..varied[2].clone() };
+        assert!(max_error(&model(&varied)) < 0.05);
+    }
+
+    #[test]
+    fn rejects_mismatched_ranges_and_reference_backbones() {
+        let custom = model(&[ConvBlockConfig::new(4)]);
+        assert!(Int8Model::new(&custom, &[[0.0, 1.0]; 2]).is_err());
+        let resnet = MalariaCNN::<B>::new(&Default::default(), 3, &Architecture::ResNet18 { base_width: 2 }, &[], &[], 2, 0.0);
+        assert!(Int8Model::new(&resnet, &[[0.0, 1.0]]).is_err());
+    }
+}
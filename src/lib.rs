@@ -0,0 +1,20 @@
+//! Malaria cell classifier: model, model bundles and inference, shared by the training CLI,
+//! the API server and services that embed the classifier
+
+#![recursion_limit = "256"]
+
+pub mod architecture;
+pub mod classifier;
+pub mod config;
+pub mod data;
+pub mod ensemble;
+pub mod int8;
+pub mod loss;
+pub mod malaria_cnn;
+pub mod preprocessing;
+pub mod reference;
+pub mod tta;
+
+pub use classifier::{CellClass, EnsemblePrediction, MalariaClassifier, MalariaEnsemble, McUncertainty, Prediction};
+pub use config::ModelConfig;
+pub use preprocessing::Preprocessor;
@@ -0,0 +1,232 @@
+//! Classification loss with optional class weighting, focal term, label smoothing and distillation
+
+use anyhow::{anyhow, bail, Result};
+use burn::tensor::{activation::log_softmax, backend::Backend, Int, Tensor};
+use crate::config::{ClassWeights, LossConfig};
+
+/// Loss resolved from `LossConfig`; class weights are fixed once from the training labels
+#[derive(Debug, Clone, Default)]
+pub struct ClassificationLoss {
+    class_weights: Option<Vec<f32>>,
+    focal_gamma: f32,
+    label_smoothing: f32,
+    /// Temperature and weight of the distillation term
+    distillation: Option<(f32, f32)>,
+}
+
+impl ClassificationLoss {
+    /// `labels` are the training labels, used to derive `ClassWeights::Balanced`
+    pub fn new(config: &LossConfig, labels: &[u8], num_classes: usize) -> Result<Self> {
+        if config.focal_gamma < 0.0 {
+            bail!("focal_gamma doit être >= 0 (reçu {})", config.focal_gamma);
+        }
+        if !(0.0..1.0).contains(&config.label_smoothing) {
+            bail!("label_smoothing doit être dans [0, 1) (reçu {})", config.label_smoothing);
+        }
+
+        let class_weights = match &config.class_weights {
+            ClassWeights::Uniform => None,
+            ClassWeights::Balanced => {
+                // Inverse frequency, scaled so that a balanced dataset gets weights of 1
+                let mut counts = vec![0usize; num_classes];
+                for &label in labels {
+                    let count = counts.get_mut(label as usize).ok_or_else(|| {
+                        anyhow!("Label {} hors de [0, {})", label, num_classes)
+                    })?;
+                    *count += 1;
+                }
+                Some(
+                    counts
+                        .iter()
+                        .map(|&count| labels.len() as f32 / (num_classes * count.max(1)) as f32)
+                        .collect(),
+                )
+            }
+            ClassWeights::Explicit(weights) => {
+                if weights.len() != num_classes {
+                    bail!("{} poids de classe pour {} classes", weights.len(), num_classes);
+                }
+                if weights.iter().any(|&w| w < 0.0) || weights.iter().all(|&w| w == 0.0) {
+                    bail!("Poids de classe invalides: {:?}", weights);
+                }
+                Some(weights.clone())
+            }
+        };
+
+        Ok(Self {
+            class_weights,
+            focal_gamma: config.focal_gamma,
+            label_smoothing: config.label_smoothing,
+            distillation: None,
+        })
+    }
+
+    /// Blends the loss with `temperature`² · KL(teacher ‖ student) on softened logits, weighted by `alpha`
+    pub fn with_distillation(mut self, temperature: f32, alpha: f32) -> Result<Self> {
+        if temperature <= 0.0 {
+            bail!("La température doit être > 0 (reçu {})", temperature);
+        }
+        if !(0.0..=1.0).contains(&alpha) {
+            bail!("alpha doit être dans [0, 1] (reçu {})", alpha);
+        }
+        self.distillation = Some((temperature, alpha));
+        Ok(self)
+    }
+
+    pub fn class_weights(&self) -> Option<&[f32]> {
+        self.class_weights.as_deref()
+    }
+
+    /// Mean loss over the batch; the distillation term only applies when `teacher_logits` are given
+    pub fn forward<B: Backend>(
+        &self,
+        logits: Tensor<B, 2>,
+        targets: Tensor<B, 1, Int>,
+        teacher_logits: Option<Tensor<B, 2>>,
+    ) -> Tensor<B, 1> {
+        match (self.distillation, teacher_logits) {
+            (Some((temperature, alpha)), Some(teacher_logits)) => {
+                let soft = distillation_loss(logits.clone(), teacher_logits, temperature);
+                self.hard_loss(logits, targets).mul_scalar(1.0 - alpha) + soft.mul_scalar(alpha)
+            }
+            _ => self.hard_loss(logits, targets),
+        }
+    }
+
+    /// Loss against the labels (weighted mean when class weights are set)
+    fn hard_loss<B: Backend>(&self, logits: Tensor<B, 2>, targets: Tensor<B, 1, Int>) -> Tensor<B, 1> {
+        let [batch_size, num_classes] = logits.dims();
+        let log_probs = log_softmax(logits, 1);
+
+        let smoothing = self.label_smoothing;
+        let soft_targets = targets
+            .clone()
+            .one_hot::<2>(num_classes)
+            .float()
+            .mul_scalar(1.0 - smoothing)
+            .add_scalar(smoothing / num_classes as f32);
+
+        let mut per_class = soft_targets * log_probs.clone().neg();
+        if self.focal_gamma > 0.0 {
+            // (1 - p)^gamma down-weights classes the model already predicts confidently
+            let modulating = log_probs.exp().neg().add_scalar(1.0).powf_scalar(self.focal_gamma);
+            per_class = per_class * modulating;
+        }
+        let per_sample = per_class.sum_dim(1).reshape([batch_size]);
+
+        match &self.class_weights {
+            None => per_sample.mean(),
+            Some(weights) => {
+                let weights = Tensor::<B, 1>::from_floats(weights.as_slice(), &per_sample.device())
+                    .select(0, targets);
+                (per_sample * weights.clone()).sum() / weights.sum()
+            }
+        }
+    }
+}
+
+/// KL(teacher ‖ student) at `temperature`, scaled by its square to keep gradients comparable
+fn distillation_loss<B: Backend>(logits: Tensor<B, 2>, teacher_logits: Tensor<B, 2>, temperature: f32) -> Tensor<B, 1> {
+    let [batch_size, _] = logits.dims();
+    let log_student = log_softmax(logits.div_scalar(temperature), 1);
+    let log_teacher = log_softmax(teacher_logits.div_scalar(temperature), 1);
+    let kl = (log_teacher.clone().exp() * (log_teacher - log_student)).sum_dim(1);
+    kl.reshape([batch_size]).mean().mul_scalar(temperature * temperature)
+}
+
+#[cfg(test)]
+mod tests {
+    use burn_ndarray::NdArray;
+
+    use super::*;
+
+    /// First sample: p = [0.75, 0.25], label 0. Second sample: p = [0.5, 0.5], label 1.
+    fn batch() -> (Tensor<NdArray, 2>, Tensor<NdArray, 1, Int>) {
+        let device = Default::default();
+        let logits = Tensor::<NdArray, 1>::from_floats([3f32.ln(), 0.0, 0.0, 0.0], &device).reshape([2, 2]);
+        let targets = Tensor::<NdArray, 1, Int>::from_ints([0, 1], &device);
+        (logits, targets)
+    }
+
+    fn loss(config: LossConfig) -> f32 {
+        let (logits, targets) = batch();
+        let loss = ClassificationLoss::new(&config, &[0, 1], 2).unwrap();
+        loss.forward(logits, targets, None).into_scalar()
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn default_is_the_mean_cross_entropy() {
+        assert_close(loss(LossConfig::default()), (-(0.75f32.ln()) + 2f32.ln()) / 2.0);
+    }
+
+    #[test]
+    fn balanced_weights_are_inverse_frequencies() {
+        let config = LossConfig { class_weights: ClassWeights::Balanced, ..LossConfig::default() };
+        let loss = ClassificationLoss::new(&config, &[0, 0, 0, 1], 2).unwrap();
+        let weights = loss.class_weights().unwrap();
+        assert_close(weights[0], 2.0 / 3.0);
+        assert_close(weights[1], 2.0);
+    }
+
+    #[test]
+    fn class_weights_give_a_weighted_mean() {
+        let config = LossConfig { class_weights: ClassWeights::Explicit(vec![1.0, 3.0]), ..LossConfig::default() };
+        assert_close(loss(config), (-(0.75f32.ln()) + 3.0 * 2f32.ln()) / 4.0);
+    }
+
+    #[test]
+    fn focal_term_scales_by_the_missing_probability() {
+        let config = LossConfig { focal_gamma: 2.0, ..LossConfig::default() };
+        assert_close(loss(config), (-(0.75f32.ln()) * 0.25f32.powi(2) + 2f32.ln() * 0.5f32.powi(2)) / 2.0);
+    }
+
+    #[test]
+    fn label_smoothing_spreads_the_targets() {
+        let config = LossConfig { label_smoothing: 0.2, ..LossConfig::default() };
+        // Targets become [0.9, 0.1] and [0.1, 0.9]
+        let first = -(0.9 * 0.75f32.ln() + 0.1 * 0.25f32.ln());
+        assert_close(loss(config), (first + 2f32.ln()) / 2.0);
+    }
+
+    #[test]
+    fn distillation_from_an_identical_teacher_only_scales_the_hard_loss() {
+        let (logits, targets) = batch();
+        let plain = ClassificationLoss::default().forward(logits.clone(), targets.clone(), None).into_scalar();
+        let distilled = ClassificationLoss::default().with_distillation(2.0, 0.25).unwrap();
+        let loss = distilled.forward(logits.clone(), targets, Some(logits)).into_scalar();
+        assert_close(loss, 0.75 * plain);
+    }
+
+    #[test]
+    fn distillation_term_is_the_scaled_kl_divergence() {
+        let device = Default::default();
+        let student = Tensor::<NdArray, 1>::from_floats([0.0, 0.0], &device).reshape([1, 2]);
+        let teacher = Tensor::<NdArray, 1>::from_floats([6f32.ln(), 0.0], &device).reshape([1, 2]);
+        // At temperature 2 the teacher gives [sqrt 6, 1] / (sqrt 6 + 1)
+        let p = 6f32.sqrt() / (6f32.sqrt() + 1.0);
+        let kl = p * (2.0 * p).ln() + (1.0 - p) * (2.0 * (1.0 - p)).ln();
+        assert_close(distillation_loss(student, teacher, 2.0).into_scalar(), 4.0 * kl);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let invalid = [
+            LossConfig { focal_gamma: -1.0, ..LossConfig::default() },
+            LossConfig { label_smoothing: 1.0, ..LossConfig::default() },
+            LossConfig { class_weights: ClassWeights::Explicit(vec![1.0]), ..LossConfig::default() },
+            LossConfig { class_weights: ClassWeights::Explicit(vec![0.0, 0.0]), ..LossConfig::default() },
+            LossConfig { class_weights: ClassWeights::Explicit(vec![1.0, -1.0]), ..LossConfig::default() },
+        ];
+        for config in &invalid {
+            assert!(ClassificationLoss::new(config, &[0, 1], 2).is_err(), "{:?}", config);
+        }
+        let balanced = LossConfig { class_weights: ClassWeights::Balanced, ..LossConfig::default() };
+        assert!(ClassificationLoss::new(&balanced, &[0, 2], 2).is_err());
+        assert!(ClassificationLoss::default().with_distillation(0.0, 0.5).is_err());
+        assert!(ClassificationLoss::default().with_distillation(2.0, 1.5).is_err());
+    }
+}
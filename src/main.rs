@@ -1,16 +1,41 @@
-mod malaria_cnn;
+#![recursion_limit = "256"]
+
+mod averaging;
 mod training;
-mod config;
-mod data;
+mod distillation;
+mod cli;
+mod cross_validation;
+mod evaluation;
+mod fine_tune;
+mod prediction;
+mod pruning;
+mod quantization;
+mod sampling;
+mod scheduler;
+mod selection;
+mod tuning;
 
 use anyhow::Result;
+use malaria_classifier::{architecture, classifier, config, data, ensemble, int8, loss, malaria_cnn, preprocessing, tta};
 use burn::backend::{wgpu::{Wgpu, WgpuDevice}, Autodiff};
 use crate::training::MalariaTrainer;
 use crate::config::ModelConfig;
+use crate::cli::{Command, ComputeBackend};
+use crate::evaluation::MalariaEvaluator;
+use crate::prediction::PredictOptions;
+use crate::pruning::MalariaPruner;
+use crate::quantization::MalariaQuantizer;
+use crate::tuning::{MalariaTuner, SearchSpace};
 
 type Backend = Autodiff<Wgpu<f32, i32>>;
+type InferenceBackend = Wgpu<f32, i32>;
+type CpuBackend = Autodiff<burn_ndarray::NdArray<f32>>;
+/// Quantized models target CPUs without a GPU
+type CpuInferenceBackend = burn_ndarray::NdArray<f32>;
 
 fn main() -> Result<()> {
+    let command = Command::from_args()?;
+
     println!("╔════════════════════════════════════════════╗");
     println!("║  🔥 BURN + WGPU - STABLE & PRODUCTION-READY ║");
     println!("╚════════════════════════════════════════════╝");
@@ -37,6 +62,48 @@ fn main() -> Result<()> {
     println!("   • Device: {:?}\n", device);
     
     // ✅ Device partagé partout
-    let trainer = MalariaTrainer::<Backend>::new(config, device);
-    trainer.run()
-}
\ No newline at end of file
+    match command {
+        Command::Train { resume } => {
+            let mut trainer = MalariaTrainer::<Backend>::new(config, device);
+            if let Some(resume) = resume {
+                trainer = trainer.resume_from(resume);
+            }
+            trainer.run()
+        }
+        Command::CrossValidate { folds, grouped, keep_models } => {
+            MalariaTrainer::<Backend>::new(config, device).cross_validate(folds, grouped, keep_models)
+        }
+        Command::Tune { space, strategy, trials, backend, seed } => {
+            let space = SearchSpace::load(&space, &config)?;
+            match backend {
+                ComputeBackend::Wgpu => {
+                    MalariaTuner::<Backend>::new(config, device, space, strategy, trials, seed).run()
+                }
+                ComputeBackend::Cpu => MalariaTuner::<CpuBackend>::new(
+                    config,
+                    burn_ndarray::NdArrayDevice::Cpu,
+                    space,
+                    strategy,
+                    trials,
+                    seed,
+                )
+                .run(),
+            }
+        }
+        Command::Evaluate { model_paths, tta, method, data } => {
+            let evaluator = MalariaEvaluator::<InferenceBackend>::new(config, device);
+            evaluator.run(&model_paths, tta, method, data.as_deref())
+        }
+        Command::Quantize { model_path, output, calibration_samples } => {
+            MalariaQuantizer::<CpuInferenceBackend>::new(config, burn_ndarray::NdArrayDevice::Cpu)
+                .run(&model_path, &output, calibration_samples)
+        }
+        Command::Prune { model_path, output, options } => {
+            MalariaPruner::<Backend>::new(config, device).run(&model_path, &output, &options)
+        }
+        Command::Predict { model_paths, images, tta, mc_samples, method } => {
+            let options = PredictOptions { tta, mc_samples, method };
+            prediction::run::<InferenceBackend>(&config, &device, &model_paths, &images, options)
+        }
+    }
+}
@@ -1,17 +1,41 @@
+use anyhow::{bail, ensure};
 use burn::{
-    module::Module,
+    module::{AutodiffModule, Ignored, Module, Param},
     nn::{
-        conv::{Conv2d, Conv2dConfig},
-        pool::{AdaptiveAvgPool2d, AdaptiveAvgPool2dConfig, MaxPool2d, MaxPool2dConfig},
-        BatchNorm, BatchNormConfig, Dropout, DropoutConfig, Linear, LinearConfig, Relu,
+        conv::Conv2d,
+        pool::{AdaptiveAvgPool2d, AdaptiveAvgPool2dConfig, MaxPool2d},
+        BatchNorm, Dropout, DropoutConfig, Linear, LinearConfig, Relu,
+    },
+    tensor::{
+        activation::{relu, softmax},
+        backend::{AutodiffBackend, Backend},
+        Distribution, ElementConversion, Int, Tensor, TensorData,
     },
-    tensor::{backend::Backend, Tensor, loss::cross_entropy_with_logits},
     train::{TrainOutput, TrainStep, ValidStep},
 };
-use crate::data::MalariaBatch;
+use serde::{Deserialize, Serialize};
+use crate::{
+    architecture::{Architecture, Backbone, ConvBlockConfig},
+    data::MalariaBatch,
+    loss::ClassificationLoss,
+};
 
+/// Layer fields are read by the int8 engine
 #[derive(Module, Debug)]
 pub struct MalariaCNN<B: Backend> {
+    pub(crate) features: Backbone<B>,
+    pub(crate) adaptive_pool: AdaptiveAvgPool2d,
+    dropout: Dropout,
+    pub(crate) hidden: Vec<Linear<B>>,
+    pub(crate) output: Linear<B>,
+    pub(crate) relu: Relu,
+    loss: Ignored<ClassificationLoss>,
+}
+
+/// Layout of the checkpoints saved before the conv stack became configurable, such as the
+/// shipped `malaria-model.bin`. Only its record is used, by [`MalariaCNN::load_legacy_record`].
+#[derive(Module, Debug)]
+pub struct LegacyMalariaCNN<B: Backend> {
     conv1: Conv2d<B>,
     bn1: BatchNorm<B>,
     conv2: Conv2d<B>,
@@ -29,75 +53,296 @@ pub struct MalariaCNN<B: Backend> {
     relu: Relu,
 }
 
+/// Summary of a Monte Carlo dropout run over several stochastic forward passes
+#[derive(Debug, Clone)]
+pub struct McDropoutOutput<B: Backend> {
+    /// Mean class probabilities across samples, `[batch, num_classes]`
+    pub mean_probs: Tensor<B, 2>,
+    /// Predictive variance of each class probability across samples, `[batch, num_classes]`
+    pub variance: Tensor<B, 2>,
+    /// Mutual information between the prediction and the dropout mask, `[batch]`
+    pub mutual_information: Tensor<B, 1>,
+}
+
+/// Class-activation map weighting used by [`MalariaCNN::grad_cam`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CamMethod {
+    /// Channel weights are the spatially averaged gradients
+    GradCam,
+    /// Channel weights use the second/third-order terms of Grad-CAM++
+    #[serde(rename = "gradcampp")]
+    GradCamPlusPlus,
+}
+
+/// Grad-CAM explanation of a single image
+#[derive(Debug, Clone)]
+pub struct GradCamOutput<B: Backend> {
+    /// Class whose score was explained
+    pub class_idx: usize,
+    /// Class probabilities, `[1, num_classes]`
+    pub probabilities: Tensor<B, 2>,
+    /// Heatmap at the last conv block's resolution, normalized to [0, 1], `[height, width]`
+    pub heatmap: Tensor<B, 2>,
+}
+
 impl<B: Backend> MalariaCNN<B> {
     pub fn new(
         device: &B::Device,
         image_channels: usize,
-        conv1_filters: usize,
-        conv2_filters: usize,
-        conv3_filters: usize,
-        fc1_units: usize,
-        fc2_units: usize,
+        architecture: &Architecture,
+        conv_blocks: &[ConvBlockConfig],
+        fc_units: &[usize],
         num_classes: usize,
         dropout_rate: f64,
     ) -> Self {
-        let conv1 = Conv2dConfig::new([image_channels, conv1_filters], [3, 3])
-            .with_padding(burn::nn::PaddingConfig2d::Same)
-            .init(device);
-
-        let conv2 = Conv2dConfig::new([conv1_filters, conv2_filters], [3, 3])
-            .with_padding(burn::nn::PaddingConfig2d::Same)
-            .init(device);
+        let features = Backbone::new(device, image_channels, architecture, conv_blocks);
 
-        let conv3 = Conv2dConfig::new([conv2_filters, conv3_filters], [3, 3])
-            .with_padding(burn::nn::PaddingConfig2d::Same)
-            .init(device);
-
-        let bn1 = BatchNormConfig::new(conv1_filters).init(device);
-        let bn2 = BatchNormConfig::new(conv2_filters).init(device);
-        let bn3 = BatchNormConfig::new(conv3_filters).init(device);
-
-        let pool1 = MaxPool2dConfig::new([2, 2]).with_strides([2, 2]).init();
-        let pool2 = MaxPool2dConfig::new([2, 2]).with_strides([2, 2]).init();
-        let pool3 = MaxPool2dConfig::new([2, 2]).with_strides([2, 2]).init();
-
-        let adaptive_pool = AdaptiveAvgPool2dConfig::new([4, 4]).init();
+        let pooled = architecture.pooled_size();
+        let adaptive_pool = AdaptiveAvgPool2dConfig::new([pooled, pooled]).init();
         let dropout = DropoutConfig::new(dropout_rate).init();
 
-        let fc_input_size = conv3_filters * 4 * 4;
-        let fc1 = LinearConfig::new(fc_input_size, fc1_units).init(device);
-        let fc2 = LinearConfig::new(fc1_units, fc2_units).init(device);
-        let fc3 = LinearConfig::new(fc2_units, num_classes).init(device);
+        let mut fc_input_size = architecture.out_channels(image_channels, conv_blocks) * pooled * pooled;
+        let hidden = fc_units
+            .iter()
+            .map(|&units| {
+                let layer = LinearConfig::new(fc_input_size, units).init(device);
+                fc_input_size = units;
+                layer
+            })
+            .collect();
+        let output = LinearConfig::new(fc_input_size, num_classes).init(device);
 
         let relu = Relu::new();
 
         Self {
-            conv1, bn1, conv2, bn2, conv3, bn3,
-            pool1, pool2, pool3, adaptive_pool,
-            dropout, fc1, fc2, fc3, relu,
+            features,
+            adaptive_pool,
+            dropout,
+            hidden,
+            output,
+            relu,
+            loss: Ignored(ClassificationLoss::default()),
         }
     }
 
+    /// Replaces the default plain cross-entropy used by the train and valid steps
+    pub fn with_loss(mut self, loss: ClassificationLoss) -> Self {
+        self.loss = Ignored(loss);
+        self
+    }
+
+    /// Loads pretrained weights; returns `true` when the output layer had another number of
+    /// classes and was kept freshly initialized
+    pub fn load_pretrained(self, record: MalariaCNNRecord<B>) -> (Self, bool) {
+        let output = self.output.clone();
+        let mut model = self.load_record(record);
+        let reinit = model.output.weight.dims() != output.weight.dims();
+        if reinit {
+            model.output = output;
+        }
+        (model, reinit)
+    }
+
+    /// Loads a checkpoint in the legacy layout: its conv/BatchNorm pairs fill the conv blocks,
+    /// `fc1` and `fc2` the hidden layers and `fc3` the output layer
+    pub fn load_legacy_record(self, legacy: LegacyMalariaCNNRecord<B>) -> anyhow::Result<Self> {
+        let LegacyMalariaCNNRecord { conv1, bn1, conv2, bn2, conv3, bn3, fc1, fc2, fc3, .. } = legacy;
+        let mut record = self.clone().into_record();
+        record.features.load_legacy(vec![(conv1, bn1), (conv2, bn2), (conv3, bn3)])?;
+        ensure!(record.hidden.len() == 2, "Legacy checkpoints have 2 hidden layers, the config {}", record.hidden.len());
+        for (i, (current, legacy)) in record.hidden.iter().zip([&fc1, &fc2]).enumerate() {
+            ensure!(
+                current.weight.dims() == legacy.weight.dims(),
+                "Hidden layer {}: legacy weights {:?}, expected {:?}",
+                i + 1,
+                legacy.weight.dims(),
+                current.weight.dims()
+            );
+        }
+        record.hidden = vec![fc1, fc2];
+        record.output = fc3;
+        Ok(self.load_record(record))
+    }
+
+    /// Convolutional feature extractor, trained as its own parameter group when fine-tuning
+    pub fn backbone(&self) -> &Backbone<B> {
+        &self.features
+    }
+
+    /// Keeps the listed filters of each conv block and the matching inputs of the first
+    /// classifier layer. Only the custom conv stack can be pruned.
+    pub fn prune(mut self, kept: &[Vec<usize>]) -> anyhow::Result<Self> {
+        let Backbone::Custom(stack) = self.features else {
+            bail!("Only the custom conv stack can be pruned");
+        };
+        ensure!(
+            kept.len() == stack.blocks().len(),
+            "{} kept filter lists for {} conv blocks",
+            kept.len(),
+            stack.blocks().len()
+        );
+        let device = self.output.weight.device();
+        let indices: Vec<Tensor<B, 1, Int>> = kept
+            .iter()
+            .map(|kept| {
+                let data = TensorData::new(kept.iter().map(|&i| i as i64).collect(), [kept.len()]);
+                Tensor::from_data(data, &device)
+            })
+            .collect();
+        self.features = Backbone::Custom(stack.prune(&indices));
+
+        // The head sees the flattened [channels, pooled, pooled] features
+        let [pooled_height, pooled_width] = self.adaptive_pool.output_size;
+        let area = pooled_height * pooled_width;
+        let last = kept.last().expect("at least one block");
+        let rows: Vec<i64> = last
+            .iter()
+            .flat_map(|&channel| (channel * area..(channel + 1) * area).map(|row| row as i64))
+            .collect();
+        let rows = Tensor::<B, 1, Int>::from_data(TensorData::new(rows.clone(), [rows.len()]), &device);
+        let first = self.hidden.first_mut().unwrap_or(&mut self.output);
+        first.weight = Param::from_tensor(first.weight.val().select(0, rows));
+        Ok(self)
+    }
+
     pub fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 2> {
-        let x = self.pool1.forward(self.relu.forward(self.bn1.forward(self.conv1.forward(x))));
-        let x = self.pool2.forward(self.relu.forward(self.bn2.forward(self.conv2.forward(x))));
-        let x = self.pool3.forward(self.relu.forward(self.bn3.forward(self.conv3.forward(x))));
+        let x = self.pooled_features(x);
+        let x = self
+            .hidden
+            .iter()
+            .fold(x, |x, layer| self.relu.forward(self.dropout.forward(layer.forward(x))));
+        self.output.forward(x)
+    }
+
+    /// Runs `num_samples` forward passes with dropout kept active, batched into one tensor.
+    ///
+    /// The convolutional stack has no dropout, so it runs once and only the
+    /// classifier head is sampled.
+    pub fn forward_mc_dropout(&self, x: Tensor<B, 4>, num_samples: usize) -> McDropoutOutput<B> {
+        assert!(num_samples > 0, "MC dropout needs at least one sample");
+        let batch_size = x.dims()[0];
+
+        let x = self.pooled_features(x).repeat_dim(0, num_samples);
+        let x = self
+            .hidden
+            .iter()
+            .fold(x, |x, layer| self.relu.forward(self.mc_dropout(layer.forward(x))));
+        let logits = self.output.forward(x);
+        let num_classes = logits.dims()[1];
+
+        // [samples, batch, classes]
+        let probs = softmax(logits, 1).reshape([num_samples, batch_size, num_classes]);
+        let mean_probs = probs.clone().mean_dim(0);
+        let variance = (probs.clone() - mean_probs.clone()).powi_scalar(2).mean_dim(0);
+
+        // Mutual information = H[mean p] - mean H[p]
+        let predictive_entropy = entropy(mean_probs.clone());
+        let expected_entropy = entropy(probs).mean_dim(0);
+        let mutual_information = (predictive_entropy - expected_entropy).clamp_min(0.0);
+
+        McDropoutOutput {
+            mean_probs: mean_probs.squeeze_dim(0),
+            variance: variance.squeeze_dim(0),
+            mutual_information: mutual_information.reshape([batch_size]),
+        }
+    }
+
+    fn pooled_features(&self, x: Tensor<B, 4>) -> Tensor<B, 2> {
+        let x = self.features.forward(x);
+        self.adaptive_pool.forward(x).flatten(1, 3)
+    }
+
+    /// Last conv block's pooling, global pooling and flattening
+    fn pool_activations(&self, x: Tensor<B, 4>) -> Tensor<B, 2> {
+        let x = self.features.pool_last(x);
         let x = self.adaptive_pool.forward(x);
-        let x = x.flatten(1, 3);
-        let x = self.relu.forward(self.dropout.forward(self.fc1.forward(x)));
-        let x = self.relu.forward(self.dropout.forward(self.fc2.forward(x)));
-        self.fc3.forward(x)
+        x.flatten(1, 3)
     }
 
-    fn compute_loss(&self, output: Tensor<B, 2>, targets: Tensor<B, 1, burn::tensor::Int>) -> Tensor<B, 1> {
-        let batch_size = targets.dims()[0];
-        let num_classes = output.dims()[1];
-        let one_hot = Tensor::<B, 2>::zeros([batch_size, num_classes], &output.device());
-        let indices = targets.clone().unsqueeze_dim(1);
-        let values = Tensor::<B, 2>::ones([batch_size, 1], &output.device());
-        let targets_one_hot = one_hot.scatter(1, indices, values);
-        cross_entropy_with_logits(output, targets_one_hot)
+    /// Dropout that stays active on non-autodiff backends, unlike `Dropout::forward`
+    fn mc_dropout(&self, x: Tensor<B, 2>) -> Tensor<B, 2> {
+        let prob_keep = 1.0 - self.dropout.prob;
+        if prob_keep >= 1.0 {
+            return x;
+        }
+        let mask = x.random_like(Distribution::Bernoulli(prob_keep));
+        (x * mask) / prob_keep
     }
+
+    fn compute_loss(
+        &self,
+        output: Tensor<B, 2>,
+        targets: Tensor<B, 1, burn::tensor::Int>,
+        teacher_logits: Option<Tensor<B, 2>>,
+    ) -> Tensor<B, 1> {
+        self.loss.forward(output, targets, teacher_logits)
+    }
+}
+
+impl<B: AutodiffBackend> MalariaCNN<B> {
+    /// Grad-CAM heatmap of `class_idx` (the predicted class when `None`) for a `[1, C, H, W]` input.
+    ///
+    /// The conv stack runs on the inner backend in eval mode; only the head after
+    /// the last conv block is differentiated, without dropout, so the result is deterministic.
+    pub fn grad_cam(
+        &self,
+        x: Tensor<B::InnerBackend, 4>,
+        class_idx: Option<usize>,
+        method: CamMethod,
+    ) -> GradCamOutput<B::InnerBackend> {
+        assert_eq!(x.dims()[0], 1, "Grad-CAM explains one image at a time");
+
+        let activations = self.valid().features.last_activations(x);
+        let leaf = Tensor::<B, 4>::from_inner(activations.clone()).require_grad();
+
+        let x = self.pool_activations(leaf.clone());
+        let x = self
+            .hidden
+            .iter()
+            .fold(x, |x, layer| self.relu.forward(layer.forward(x)));
+        let logits = self.output.forward(x);
+
+        let probabilities = softmax(logits.clone().inner(), 1);
+        let class_idx = class_idx.unwrap_or_else(|| {
+            probabilities.clone().argmax(1).into_scalar().elem::<i64>() as usize
+        });
+        let score = logits.slice([0..1, class_idx..class_idx + 1]).sum();
+        let gradients = leaf
+            .grad(&score.backward())
+            .expect("last conv block activations are tracked");
+
+        // [1, channels, 1, 1]
+        let weights = match method {
+            CamMethod::GradCam => gradients.mean_dim(2).mean_dim(3),
+            CamMethod::GradCamPlusPlus => {
+                let grads_2 = gradients.clone().powi_scalar(2);
+                let grads_3 = gradients.clone().powi_scalar(3);
+                let activation_sum = activations.clone().sum_dim(2).sum_dim(3);
+                let denominator = grads_2.clone() * 2.0 + activation_sum * grads_3;
+                let denominator = denominator.clone().mask_fill(denominator.equal_elem(0.0), 1.0);
+                let alpha = grads_2 / denominator;
+                (alpha * relu(gradients)).sum_dim(2).sum_dim(3)
+            }
+        };
+
+        let cam = relu((weights * activations).sum_dim(1));
+        let [_, _, height, width] = cam.dims();
+        let cam = cam.reshape([height, width]);
+        let max = cam.clone().max().clamp_min(1e-12).reshape([1, 1]);
+
+        GradCamOutput {
+            class_idx,
+            probabilities,
+            heatmap: cam / max,
+        }
+    }
+}
+
+/// Entropy over the last dimension, keeping it as size 1
+fn entropy<B: Backend>(probs: Tensor<B, 3>) -> Tensor<B, 3> {
+    let p = probs.clone().clamp_min(1e-12);
+    (probs * p.log()).sum_dim(2).neg()
 }
 
 #[derive(Debug, Clone)]
@@ -130,7 +375,7 @@ impl<B: Backend> burn::train::metric::Adaptor<burn::train::metric::AccuracyInput
 impl<B: burn::tensor::backend::AutodiffBackend> TrainStep<MalariaBatch<B>, ClassificationOutput<B>> for MalariaCNN<B> {
     fn step(&self, batch: MalariaBatch<B>) -> TrainOutput<ClassificationOutput<B>> {
         let output = self.forward(batch.images);
-        let loss = self.compute_loss(output.clone(), batch.labels.clone());
+        let loss = self.compute_loss(output.clone(), batch.labels.clone(), batch.teacher_logits);
         let grads = loss.backward();
 
         TrainOutput::new(
@@ -148,7 +393,7 @@ impl<B: burn::tensor::backend::AutodiffBackend> TrainStep<MalariaBatch<B>, Class
 impl<B: Backend> ValidStep<MalariaBatch<B>, ClassificationOutput<B>> for MalariaCNN<B> {
     fn step(&self, batch: MalariaBatch<B>) -> ClassificationOutput<B> {
         let output = self.forward(batch.images);
-        let loss = self.compute_loss(output.clone(), batch.labels.clone());
+        let loss = self.compute_loss(output.clone(), batch.labels.clone(), batch.teacher_logits);
 
         ClassificationOutput {
             loss: loss.detach(),
@@ -0,0 +1,90 @@
+//! `predict` command: classifies image files through the inference library
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use burn::tensor::backend::Backend;
+use image::DynamicImage;
+use crate::{
+    classifier::{MalariaClassifier, MalariaEnsemble},
+    config::ModelConfig,
+    ensemble::EnsembleMethod,
+    tta::TtaMode,
+};
+
+/// How each image is run through the models
+#[derive(Debug, Clone, Copy)]
+pub struct PredictOptions {
+    pub tta: Option<TtaMode>,
+    pub mc_samples: Option<usize>,
+    pub method: EnsembleMethod,
+}
+
+/// Prints the class and probabilities of every image, plus the spread of the members for an ensemble
+pub fn run<B: Backend>(
+    config: &ModelConfig,
+    device: &B::Device,
+    model_paths: &[String],
+    images: &[PathBuf],
+    options: PredictOptions,
+) -> Result<()> {
+    let mut classifiers = model_paths
+        .iter()
+        .map(|path| load_classifier::<B>(config, device, path, options))
+        .collect::<Result<Vec<_>>>()?;
+    let decoded = images
+        .iter()
+        .map(|path| image::open(path).map_err(|e| anyhow!("Lecture de {}: {}", path.display(), e)))
+        .collect::<Result<Vec<DynamicImage>>>()?;
+
+    if classifiers.len() == 1 {
+        let predictions = classifiers.remove(0).predict_batch(&decoded)?;
+        for (path, prediction) in images.iter().zip(predictions) {
+            let [p0, p1] = prediction.probabilities;
+            print!("🔬 {}: {} (Uninfected {:.2}%, Parasitized {:.2}%)", path.display(), prediction.class.name(), p0 * 100.0, p1 * 100.0);
+            match prediction.uncertainty {
+                Some(u) => println!(", information mutuelle {:.4}", u.mutual_information),
+                None => println!(),
+            }
+        }
+        return Ok(());
+    }
+
+    println!("🧩 Ensemble de {} modèles ({:?})", classifiers.len(), options.method);
+    let predictions = MalariaEnsemble::new(classifiers, options.method)?.predict_batch(&decoded)?;
+    for (path, prediction) in images.iter().zip(predictions) {
+        let [p0, p1] = prediction.prediction.probabilities;
+        println!(
+            "🔬 {}: {} (Uninfected {:.2}%, Parasitized {:.2}%), désaccord {:.0}%",
+            path.display(),
+            prediction.prediction.class.name(),
+            p0 * 100.0,
+            p1 * 100.0,
+            prediction.disagreement * 100.0
+        );
+    }
+    Ok(())
+}
+
+/// Bundle saved next to the weights, or the command-line config when there is none
+fn load_classifier<B: Backend>(
+    config: &ModelConfig,
+    device: &B::Device,
+    model_path: &str,
+    options: PredictOptions,
+) -> Result<MalariaClassifier<B>> {
+    let model_path = Path::new(model_path).with_extension("");
+    let classifier = if model_path.with_extension("json").exists() {
+        MalariaClassifier::from_bundle(&model_path, device)?
+    } else {
+        MalariaClassifier::from_checkpoint(&model_path, config.clone(), device)?
+    };
+    let classifier = match options.tta {
+        Some(tta) => classifier.with_tta(tta),
+        None => classifier,
+    };
+    match options.mc_samples {
+        Some(samples) => classifier.with_mc_samples(samples),
+        None => Ok(classifier),
+    }
+}
@@ -0,0 +1,330 @@
+//! Image preprocessing shared by training, evaluation and serving
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use image::{imageops::{self, FilterType}, DynamicImage, ImageReader, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+
+/// Interpolation used to resize images to the model input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeFilter {
+    Nearest,
+    /// Bilinear
+    #[default]
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => Self::Nearest,
+            ResizeFilter::Triangle => Self::Triangle,
+            ResizeFilter::CatmullRom => Self::CatmullRom,
+            ResizeFilter::Gaussian => Self::Gaussian,
+            ResizeFilter::Lanczos3 => Self::Lanczos3,
+        }
+    }
+}
+
+/// How the image is brought to the model input size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResizeStrategy {
+    /// Stretched to the input size, ignoring the aspect ratio
+    #[default]
+    Exact,
+    /// Fitted inside the input size, the borders filled with `pad_color` (RGB)
+    Letterbox { pad_color: [u8; 3] },
+    /// Central region of the input size, without scaling (black borders if the image is smaller)
+    CenterCrop,
+    /// Scaled so that its shorter side is `shorter_side` pixels, then center-cropped
+    ShorterSideCrop { shorter_side: u32 },
+}
+
+/// Channel means of the ImageNet training set, expected by backbones pretrained on it
+pub const IMAGENET_MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+/// Channel standard deviations of the ImageNet training set
+pub const IMAGENET_STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+/// Mapping of the pixel values once scaled to [0, 1]
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PixelNormalization {
+    /// Values left in [0, 1]
+    #[default]
+    UnitRange,
+    /// `(value - mean) / std`, one entry per channel
+    MeanStd { mean: Vec<f32>, std: Vec<f32> },
+    /// Mean and standard deviation of the training images: training computes them and
+    /// saves them in the bundle as `mean_std`
+    Dataset,
+    /// ImageNet statistics, for backbones pretrained on it (3 channels)
+    #[serde(rename = "imagenet")]
+    ImageNet,
+}
+
+/// Where the model channels come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChannelMode {
+    /// RGB for a 3-channel model, luminance for a single channel
+    #[default]
+    Auto,
+    /// Luminance, repeated on every model channel
+    Grayscale,
+    /// One channel of the image (0 red, 1 green, 2 blue), repeated on every model channel
+    Select { index: usize },
+}
+
+/// Preprocessing saved in the model bundle; the input size and channel count are the
+/// `image_*` fields of the model config
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreprocessingConfig {
+    pub resize: ResizeStrategy,
+    pub filter: ResizeFilter,
+    pub channel_mode: ChannelMode,
+    /// `UnitRange` or `MeanStd`, the other variants being resolved by `new`
+    pub normalization: PixelNormalization,
+}
+
+impl PreprocessingConfig {
+    /// Same resize and channels with the pixels left in [0, 1], as needed to compute statistics
+    pub fn unnormalized(&self) -> Self {
+        Self { normalization: PixelNormalization::UnitRange, ..self.clone() }
+    }
+}
+
+/// Turns decoded images into the `[channels, height, width]` floats the model expects
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preprocessor {
+    pub height: usize,
+    pub width: usize,
+    /// 3 for RGB, 1 for grayscale
+    pub channels: usize,
+    pub resize: ResizeStrategy,
+    pub filter: ResizeFilter,
+    pub channel_mode: ChannelMode,
+    /// `UnitRange` or `MeanStd`, the other variants being resolved by `new`
+    pub normalization: PixelNormalization,
+}
+
+impl Preprocessor {
+    pub fn new(height: usize, width: usize, channels: usize, config: &PreprocessingConfig) -> Result<Self> {
+        if height == 0 || width == 0 {
+            bail!("Taille d'image invalide: {}x{}", width, height);
+        }
+        if !matches!(channels, 1 | 3) {
+            bail!("Nombre de canaux non pris en charge: {} (attendu: 1 ou 3)", channels);
+        }
+        if let ResizeStrategy::ShorterSideCrop { shorter_side } = config.resize {
+            if (shorter_side as usize) < height.max(width) {
+                bail!("Le petit côté ({} pixels) doit couvrir l'entrée {}x{}", shorter_side, width, height);
+            }
+        }
+        if let ChannelMode::Select { index } = config.channel_mode {
+            if index > 2 {
+                bail!("Canal {} inexistant (attendu: 0 rouge, 1 vert ou 2 bleu)", index);
+            }
+        }
+        let normalization = match &config.normalization {
+            PixelNormalization::Dataset => {
+                bail!("Statistiques de normalisation non calculées: l'entraînement remplace `dataset` par `mean_std`")
+            }
+            PixelNormalization::ImageNet if channels != 3 => {
+                bail!("Les statistiques ImageNet demandent 3 canaux (reçu {})", channels)
+            }
+            PixelNormalization::ImageNet => {
+                PixelNormalization::MeanStd { mean: IMAGENET_MEAN.to_vec(), std: IMAGENET_STD.to_vec() }
+            }
+            normalization => normalization.clone(),
+        };
+        if let PixelNormalization::MeanStd { mean, std } = &normalization {
+            if mean.len() != channels || std.len() != channels {
+                bail!("La normalisation demande {} moyennes et écarts-types (reçu {} et {})", channels, mean.len(), std.len());
+            }
+            if std.iter().any(|&s| s <= 0.0 || !s.is_finite()) {
+                bail!("Écarts-types de normalisation invalides: {:?}", std);
+            }
+        }
+        Ok(Self {
+            height,
+            width,
+            channels,
+            resize: config.resize,
+            filter: config.filter,
+            channel_mode: config.channel_mode,
+            normalization,
+        })
+    }
+
+    /// Number of floats produced per image
+    pub fn image_size(&self) -> usize {
+        self.channels * self.height * self.width
+    }
+
+    pub fn load(&self, path: &Path) -> Result<Vec<f32>> {
+        let image = ImageReader::open(path)
+            .with_context(|| format!("Lecture de {}", path.display()))?
+            .decode()
+            .with_context(|| format!("Décodage de {}", path.display()))?;
+        Ok(self.process(&image))
+    }
+
+    /// Brings the image to the input size with the resize strategy, before any channel conversion
+    pub fn resize_image(&self, image: &DynamicImage) -> DynamicImage {
+        let (width, height) = (self.width as u32, self.height as u32);
+        let filter = self.filter.into();
+        match self.resize {
+            ResizeStrategy::Exact => image.resize_exact(width, height, filter),
+            ResizeStrategy::Letterbox { pad_color } => pad(&image.resize(width, height, filter), width, height, pad_color),
+            ResizeStrategy::CenterCrop => pad(&center_crop(image, width, height), width, height, [0, 0, 0]),
+            ResizeStrategy::ShorterSideCrop { shorter_side } => {
+                let scale = shorter_side as f64 / image.width().min(image.height()) as f64;
+                let scaled = |side: u32| ((side as f64 * scale).round() as u32).max(shorter_side);
+                let resized = image.resize_exact(scaled(image.width()), scaled(image.height()), filter);
+                center_crop(&resized, width, height)
+            }
+        }
+    }
+
+    /// Where a `width` x `height` image lands on the model input: left, top, width and height in
+    /// input pixels, reaching past the input where the image is cropped
+    pub fn placement(&self, width: u32, height: u32) -> [f64; 4] {
+        let (input_width, input_height) = (self.width as u32, self.height as u32);
+        let (placed_width, placed_height) = match self.resize {
+            ResizeStrategy::Exact => (input_width, input_height),
+            ResizeStrategy::Letterbox { .. } => {
+                // Same rounding as `DynamicImage::resize`
+                let ratio = (input_width as f64 / width as f64).min(input_height as f64 / height as f64);
+                let fitted = |side: u32| ((side as f64 * ratio).round() as u32).max(1);
+                (fitted(width), fitted(height))
+            }
+            ResizeStrategy::CenterCrop => (width, height),
+            ResizeStrategy::ShorterSideCrop { shorter_side } => {
+                let scale = shorter_side as f64 / width.min(height) as f64;
+                let scaled = |side: u32| ((side as f64 * scale).round() as u32).max(shorter_side);
+                (scaled(width), scaled(height))
+            }
+        };
+        // Halved in integers, as `pad` and `center_crop` do
+        let offset = |placed: u32, input: u32| {
+            if placed > input { -(((placed - input) / 2) as f64) } else { ((input - placed) / 2) as f64 }
+        };
+        [offset(placed_width, input_width), offset(placed_height, input_height), placed_width as f64, placed_height as f64]
+    }
+
+    /// Resamples a row-major map computed over the model input (a Grad-CAM heatmap) onto a
+    /// `width` x `height` grid spread over the original `image_width` x `image_height` image,
+    /// undoing the padding and crop of the resize strategy; `None` where the crop dropped the image
+    pub fn map_to_image(
+        &self,
+        map: &[f32],
+        map_width: usize,
+        (image_width, image_height): (u32, u32),
+        width: usize,
+        height: usize,
+    ) -> Vec<Option<f32>> {
+        let map_height = map.len() / map_width;
+        let [left, top, placed_width, placed_height] = self.placement(image_width, image_height);
+        // Input position of a grid cell center, then the map coordinate it falls on
+        let sample = |cell: usize, cells: usize, origin: f64, placed: f64, input: usize, map_cells: usize| {
+            let position = origin + (cell as f64 + 0.5) / cells as f64 * placed;
+            (0.0..input as f64)
+                .contains(&position)
+                .then(|| (position / input as f64 * map_cells as f64 - 0.5).clamp(0.0, (map_cells - 1) as f64))
+        };
+        let columns: Vec<_> = (0..width).map(|x| sample(x, width, left, placed_width, self.width, map_width)).collect();
+
+        let mut values = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = sample(y, height, top, placed_height, self.height, map_height);
+            values.extend(columns.iter().map(|&column| {
+                let (x, y) = (column?, row?);
+                let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+                let (x1, y1) = ((x0 + 1).min(map_width - 1), (y0 + 1).min(map_height - 1));
+                let (fx, fy) = ((x - x0 as f64) as f32, (y - y0 as f64) as f32);
+                let at = |x: usize, y: usize| map[y * map_width + x];
+                let upper = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+                let lower = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+                Some(upper * (1.0 - fy) + lower * fy)
+            }));
+        }
+        values
+    }
+
+    /// Resizes, converts to the channel count, and lays the normalized pixels out channel by channel
+    pub fn process(&self, image: &DynamicImage) -> Vec<f32> {
+        let image = self.resize_image(image);
+        // Single-channel sources are repeated on every model channel
+        let (raw, source_channels) = match (self.channel_mode, self.channels) {
+            (ChannelMode::Auto, 3) => (image.to_rgb8().into_raw(), 3),
+            (ChannelMode::Auto | ChannelMode::Grayscale, _) => (image.to_luma8().into_raw(), 1),
+            (ChannelMode::Select { index }, _) => (image.to_rgb8().into_raw().into_iter().skip(index).step_by(3).collect(), 1),
+        };
+
+        let frame = self.height * self.width;
+        let mut chw = vec![0.0f32; frame * self.channels];
+        for (i, pixel) in raw.chunks_exact(source_channels).enumerate() {
+            for c in 0..self.channels {
+                chw[i + c * frame] = pixel[c % source_channels] as f32 / 255.0;
+            }
+        }
+
+        if let PixelNormalization::MeanStd { mean, std } = &self.normalization {
+            for (c, plane) in chw.chunks_exact_mut(frame).enumerate() {
+                plane.iter_mut().for_each(|value| *value = (*value - mean[c]) / std[c]);
+            }
+        }
+        chw
+    }
+
+    /// Per-channel mean and standard deviation of the preprocessed images
+    pub fn channel_statistics(&self, paths: &[impl AsRef<Path>]) -> Result<(Vec<f32>, Vec<f32>)> {
+        if paths.is_empty() {
+            bail!("Aucune image pour calculer les statistiques de normalisation");
+        }
+        let frame = self.height * self.width;
+        let mut sums = vec![0.0f64; self.channels];
+        let mut squares = vec![0.0f64; self.channels];
+        for path in paths {
+            let pixels = self.load(path.as_ref())?;
+            for (c, plane) in pixels.chunks_exact(frame).enumerate() {
+                for &value in plane {
+                    sums[c] += value as f64;
+                    squares[c] += (value as f64).powi(2);
+                }
+            }
+        }
+
+        let count = (paths.len() * frame) as f64;
+        let mean: Vec<f64> = sums.iter().map(|sum| sum / count).collect();
+        let std: Vec<f64> = squares.iter().zip(&mean).map(|(square, m)| (square / count - m * m).max(0.0).sqrt()).collect();
+        if let Some(c) = std.iter().position(|&s| s < 1e-6) {
+            bail!("Le canal {} est constant sur les images: écart-type nul", c);
+        }
+        Ok((mean.iter().map(|&m| m as f32).collect(), std.iter().map(|&s| s as f32).collect()))
+    }
+}
+
+/// Central `width` x `height` region, smaller when the image is
+fn center_crop(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let (crop_width, crop_height) = (width.min(image.width()), height.min(image.height()));
+    image.crop_imm((image.width() - crop_width) / 2, (image.height() - crop_height) / 2, crop_width, crop_height)
+}
+
+/// Centers the image on a `width` x `height` canvas of `color`
+fn pad(image: &DynamicImage, width: u32, height: u32, color: [u8; 3]) -> DynamicImage {
+    if (image.width(), image.height()) == (width, height) {
+        return image.clone();
+    }
+    let mut canvas = RgbImage::from_pixel(width, height, Rgb(color));
+    let (x, y) = ((width - image.width()) / 2, (height - image.height()) / 2);
+    imageops::overlay(&mut canvas, &image.to_rgb8(), x.into(), y.into());
+    canvas.into()
+}
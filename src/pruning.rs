@@ -0,0 +1,323 @@
+//! Structured pruning: removes whole conv filters and everything that depends on them
+
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use burn::{
+    module::Module,
+    record::{BinFileRecorder, FullPrecisionSettings, Recorder},
+    tensor::backend::{AutodiffBackend, Backend},
+};
+use crate::{
+    architecture::{Backbone, ConvBlockConfig, ConvStack, Normalization},
+    classifier::{load_record, InferenceModel},
+    config::{FineTuneConfig, ModelConfig},
+    data::{MalariaBatch, MalariaBatcher, MalariaDataset},
+    evaluation::{batches, measure},
+    malaria_cnn::MalariaCNN,
+    training::MalariaTrainer,
+};
+
+/// How the filters of a block are ranked before the lowest ones are removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PruningCriterion {
+    /// L1 norm of the filter weights
+    L1Norm,
+    /// Mean absolute activation of the filter on calibration images
+    Activation,
+}
+
+impl FromStr for PruningCriterion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "l1" => Ok(Self::L1Norm),
+            "activation" => Ok(Self::Activation),
+            other => Err(anyhow!("Critère inconnu: {} (attendu: l1, activation)", other)),
+        }
+    }
+}
+
+/// Pruning run read from the command line
+#[derive(Debug, Clone)]
+pub struct PruningOptions {
+    /// Fraction of the filters removed from each block
+    pub ratio: f64,
+    pub criterion: PruningCriterion,
+    /// Epochs of fine-tuning after pruning (0 to skip)
+    pub fine_tune_epochs: usize,
+    /// Training images the activation statistics are computed on
+    pub calibration_samples: usize,
+}
+
+pub struct MalariaPruner<B: AutodiffBackend> {
+    config: ModelConfig,
+    device: B::Device,
+}
+
+impl<B: AutodiffBackend> MalariaPruner<B> {
+    pub fn new(config: ModelConfig, device: B::Device) -> Self {
+        Self { config, device }
+    }
+
+    pub fn run(&self, model_path: &str, output: &str, options: &PruningOptions) -> Result<()> {
+        println!("✂️  Élagage du modèle: {} ({:.0}% des filtres, {:?})", model_path, options.ratio * 100.0, options.criterion);
+        if !(0.0..1.0).contains(&options.ratio) {
+            bail!("Le ratio d'élagage doit être dans [0, 1) (reçu {})", options.ratio);
+        }
+
+        let model_path = Path::new(model_path).with_extension("");
+        let bundle = model_path.with_extension("json");
+        let config = if bundle.exists() { ModelConfig::load(&bundle)? } else { self.config.clone() };
+        if config.quantization.is_some() {
+            bail!("{} est quantifié: élaguer le modèle f32 puis le quantifier", model_path.display());
+        }
+
+        let record = load_record(&model_path, &config, &self.device)?;
+        let model: MalariaCNN<B::InnerBackend> = MalariaCNN::new(
+            &self.device,
+            config.image_channels,
+            &config.architecture,
+            &config.conv_blocks,
+            &config.fc_units,
+            config.num_classes,
+            config.dropout_rate,
+        )
+        .load_record(record);
+        let Backbone::Custom(stack) = model.backbone() else {
+            bail!("Seule l'architecture custom peut être élaguée ({:?})", config.architecture);
+        };
+
+        // Same seed and ratio as training: statistics on train images, measures on the held-out split
+        let full_dataset = MalariaDataset::new("data", config.preprocessor()?, config.use_cache)?;
+        let (train_dataset, valid_dataset) = full_dataset.split(0.8);
+        let batcher = MalariaBatcher::<B::InnerBackend>::new(full_dataset.preprocessor.clone());
+
+        let scores = match options.criterion {
+            PruningCriterion::L1Norm => stack
+                .blocks()
+                .iter()
+                .map(|block| block.filter_norms().into_data().to_vec::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| anyhow!("Lecture des normes: {:?}", e))?,
+            PruningCriterion::Activation => {
+                let calibration = batches(&batcher, &train_dataset, options.calibration_samples, config.batch_size, &self.device);
+                activation_scores(stack, calibration)?
+            }
+        };
+        let kept = kept_filters(&config, &scores, options.ratio);
+
+        let mut pruned_config = ModelConfig { fine_tune: None, ..config.clone() };
+        for (block, kept) in pruned_config.conv_blocks.iter_mut().zip(&kept) {
+            block.filters = kept.len();
+        }
+        let pruned = model.clone().prune(&kept)?;
+
+        let results = measure(
+            &[&InferenceModel::Float(model.clone()), &InferenceModel::Float(pruned.clone())],
+            batches(&batcher, &valid_dataset, valid_dataset.len(), config.batch_size, &self.device),
+        );
+        let ((accuracy_before, time_before), (accuracy_after, time_after)) = (results[0], results[1]);
+        let (params_before, params_after) = (model.num_params(), pruned.num_params());
+
+        let output = Path::new(output).with_extension("");
+        BinFileRecorder::<FullPrecisionSettings>::new().record(pruned.into_record(), output.clone())?;
+        fs::write(output.with_extension("json"), serde_json::to_string_pretty(&pruned_config)?)?;
+
+        println!("📐 Filtres par bloc:");
+        for (i, (before, after)) in config.conv_blocks.iter().zip(&pruned_config.conv_blocks).enumerate() {
+            println!("   - Bloc {}: {} → {}", i + 1, before.filters, after.filters);
+        }
+        println!("📊 Résultats sur {} images de validation:", valid_dataset.len());
+        println!(
+            "   - Paramètres: {} → {} ({:.1}%)",
+            params_before,
+            params_after,
+            params_after as f64 / params_before.max(1) as f64 * 100.0
+        );
+        println!("   - Précision: {:.2}% → {:.2}%", accuracy_before * 100.0, accuracy_after * 100.0);
+        println!(
+            "   - Inférence: {:.1} ms → {:.1} ms, accélération x{:.2}",
+            time_before * 1000.0,
+            time_after * 1000.0,
+            time_before / time_after.max(f64::EPSILON)
+        );
+
+        if options.fine_tune_epochs > 0 {
+            println!("\n🔧 Fine-tuning du modèle élagué ({} époques)", options.fine_tune_epochs);
+            let fine_tune_config = ModelConfig {
+                num_epochs: options.fine_tune_epochs,
+                fine_tune: Some(FineTuneConfig {
+                    pretrained_path: output.with_extension("bin").display().to_string(),
+                    key_remap: Vec::new(),
+                    top_level_key: None,
+                    freeze_backbone_epochs: 0,
+                    backbone_lr_scale: 1.0,
+                }),
+                ..pruned_config.clone()
+            };
+            let metrics = MalariaTrainer::<B>::new(fine_tune_config, self.device.clone())
+                .fit_split(train_dataset, valid_dataset, &output, None)?;
+            // The exported bundle is the pruned architecture, not the fine-tuning run
+            fs::write(output.with_extension("json"), serde_json::to_string_pretty(&pruned_config)?)?;
+            println!("   - Précision après fine-tuning: {:.2}%", metrics.accuracy);
+        }
+        println!("💾 Modèle élagué: {}", output.with_extension("bin").display());
+
+        Ok(())
+    }
+}
+
+/// Mean absolute activation of each filter of each block over the calibration batches
+fn activation_scores<B: Backend>(
+    stack: &ConvStack<B>,
+    batches: impl Iterator<Item = MalariaBatch<B>>,
+) -> Result<Vec<Vec<f32>>> {
+    let mut totals: Vec<Vec<f32>> = Vec::new();
+    for batch in batches {
+        for (block, activations) in stack.block_activations(batch.images).into_iter().enumerate() {
+            let [_, channels, _, _] = activations.dims();
+            let means = activations
+                .abs()
+                .mean_dim(0)
+                .mean_dim(2)
+                .mean_dim(3)
+                .reshape([channels])
+                .into_data()
+                .to_vec::<f32>()
+                .map_err(|e| anyhow!("Lecture des activations: {:?}", e))?;
+            match totals.get_mut(block) {
+                Some(total) => total.iter_mut().zip(means).for_each(|(total, mean)| *total += mean),
+                None => totals.push(means),
+            }
+        }
+    }
+    Ok(totals)
+}
+
+/// Indices (in increasing order) of the filters each block keeps.
+///
+/// Blocks with an identity shortcut keep the channels of their input. Blocks with a projection
+/// never end up with as many filters as inputs, which would drop the projection on reload.
+fn kept_filters(config: &ModelConfig, scores: &[Vec<f32>], ratio: f64) -> Vec<Vec<usize>> {
+    let mut kept: Vec<Vec<usize>> = Vec::with_capacity(scores.len());
+    for (i, (block, scores)) in config.conv_blocks.iter().zip(scores).enumerate() {
+        let inputs = kept.last().map_or(config.image_channels, |k| k.len());
+        let indices = if identity_shortcut(config, i) {
+            kept.last().cloned().unwrap_or_else(|| (0..block.filters).collect())
+        } else {
+            let step = count_step(config, i);
+            let mut count = keep_count(block.filters, ratio, step);
+            if block.residual && block.stride == 1 && count == inputs {
+                count = if count + step <= block.filters { count + step } else { count.saturating_sub(step).max(step) };
+            }
+            let mut ranked: Vec<usize> = (0..block.filters).collect();
+            ranked.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+            ranked.truncate(count);
+            ranked.sort_unstable();
+            ranked
+        };
+        kept.push(indices);
+    }
+    kept
+}
+
+/// Whether block `i` adds its input unchanged, so it keeps the filters of the block before it
+fn identity_shortcut(config: &ModelConfig, i: usize) -> bool {
+    let block = &config.conv_blocks[i];
+    let in_channels = i.checked_sub(1).map_or(config.image_channels, |prev| config.conv_blocks[prev].filters);
+    block.residual && block.stride == 1 && in_channels == block.filters
+}
+
+/// Multiple the kept filters of block `i` must be: its GroupNorm group count, and that of the
+/// identity-shortcut blocks right after it, which keep the same channels
+fn count_step(config: &ModelConfig, i: usize) -> usize {
+    let following = (i + 1..config.conv_blocks.len()).take_while(|&j| identity_shortcut(config, j));
+    following.fold(group_size(&config.conv_blocks[i]), |step, j| lcm(step, group_size(&config.conv_blocks[j])))
+}
+
+/// Filters left after removing `ratio` of `filters`, rounded up to a multiple of `step`
+fn keep_count(filters: usize, ratio: f64, step: usize) -> usize {
+    let count = ((filters as f64) * (1.0 - ratio)).ceil() as usize;
+    (count.div_ceil(step) * step).clamp(step, filters)
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    a / x * b
+}
+
+fn group_size(block: &ConvBlockConfig) -> usize {
+    match block.norm {
+        Normalization::GroupNorm { groups } => groups,
+        Normalization::BatchNorm | Normalization::None => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn_ndarray::NdArray;
+
+    use super::*;
+    use crate::architecture::Architecture;
+
+    fn config(conv_blocks: Vec<ConvBlockConfig>) -> ModelConfig {
+        ModelConfig { image_channels: 3, conv_blocks, ..ModelConfig::default() }
+    }
+
+    /// Filter `i` scores `i`, so the highest indices are kept
+    fn scores(config: &ModelConfig) -> Vec<Vec<f32>> {
+        config.conv_blocks.iter().map(|block| (0..block.filters).map(|i| i as f32).collect()).collect()
+    }
+
+    #[test]
+    fn keep_count_rounds_up_to_the_step() {
+        assert_eq!(keep_count(32, 0.3, 1), 23);
+        assert_eq!(keep_count(32, 0.3, 8), 24);
+        assert_eq!(keep_count(16, 0.0, 1), 16);
+        assert_eq!(keep_count(16, 0.99, 4), 4);
+    }
+
+    #[test]
+    fn keeps_the_highest_scores_in_order() {
+        let config = config(vec![ConvBlockConfig::new(4)]);
+        let kept = kept_filters(&config, &[vec![0.1, 0.9, 0.5, 0.3]], 0.5);
+        assert_eq!(kept, vec![vec![1, 2]]);
+    }
+
+    #[test]
+    fn identity_shortcut_keeps_a_multiple_of_its_groups() {
+        let grouped = ConvBlockConfig { residual: true, norm: Normalization::GroupNorm { groups: 4 }, ..ConvBlockConfig::new(16) };
+        let config = config(vec![ConvBlockConfig::new(16), grouped]);
+        // 16 × 0.6 = 9.6 would keep 10 filters, which 4 groups cannot split
+        let kept = kept_filters(&config, &scores(&config), 0.4);
+        assert_eq!(kept[0].len(), 12);
+        assert_eq!(kept[1], kept[0]);
+    }
+
+    #[test]
+    fn prune_rejects_other_backbones_and_mismatched_lists() {
+        let device = Default::default();
+        let model = |architecture: &Architecture, conv_blocks: &[ConvBlockConfig]| {
+            MalariaCNN::<NdArray>::new(&device, 3, architecture, conv_blocks, &[8], 2, 0.0)
+        };
+        let resnet = Architecture::ResNet18 { base_width: 2 };
+        assert!(model(&resnet, &[]).prune(&[]).is_err());
+        let custom = [ConvBlockConfig::new(4), ConvBlockConfig::new(4)];
+        assert!(model(&Architecture::Custom, &custom).prune(&[vec![0, 1]]).is_err());
+        assert!(model(&Architecture::Custom, &custom).prune(&[vec![0, 1], vec![2]]).is_ok());
+    }
+
+    #[test]
+    fn projection_never_matches_its_inputs() {
+        let residual = ConvBlockConfig { residual: true, ..ConvBlockConfig::new(16) };
+        let config = config(vec![ConvBlockConfig::new(15), residual]);
+        let kept = kept_filters(&config, &scores(&config), 0.5);
+        assert_eq!(kept[0].len(), 8);
+        assert_eq!(kept[1].len(), 9);
+    }
+}
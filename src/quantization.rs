@@ -0,0 +1,131 @@
+//! Post-training int8 quantization of a checkpoint for low-power CPUs
+
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail};
+use burn::{
+    module::{Module, ModuleMapper, Param, Quantizer},
+    record::{BinFileRecorder, FullPrecisionSettings, Recorder},
+    tensor::{
+        backend::Backend,
+        quantization::{Calibration, QuantLevel, QuantParam, QuantScheme, QuantStore, QuantValue},
+        Tensor,
+    },
+};
+use crate::{
+    architecture::Architecture,
+    classifier::{load_inference_model, load_record, InferenceModel},
+    config::{ModelConfig, QuantizationConfig},
+    data::{MalariaBatcher, MalariaDataset},
+    evaluation::{batches, measure},
+    int8::calibrate,
+    malaria_cnn::MalariaCNN,
+};
+
+/// Quantizes conv and linear weights (rank ≥ 2) to int8; biases and BatchNorm stay in f32
+struct WeightQuantizer(Quantizer);
+
+impl<B: Backend> ModuleMapper<B> for WeightQuantizer {
+    fn map_float<const D: usize>(&mut self, param: Param<Tensor<B, D>>) -> Param<Tensor<B, D>> {
+        if D < 2 {
+            return param;
+        }
+        ModuleMapper::<B>::map_float(&mut self.0, param)
+    }
+}
+
+pub struct MalariaQuantizer<B: Backend> {
+    config: ModelConfig,
+    device: B::Device,
+}
+
+impl<B: Backend> MalariaQuantizer<B> {
+    pub fn new(config: ModelConfig, device: B::Device) -> Self {
+        Self { config, device }
+    }
+
+    pub fn run(&self, model_path: &str, output: &str, calibration_samples: usize) -> anyhow::Result<()> {
+        println!("🗜️  Quantification int8 du modèle: {}", model_path);
+
+        let model_path = Path::new(model_path).with_extension("");
+        let bundle = model_path.with_extension("json");
+        let config = if bundle.exists() { ModelConfig::load(&bundle)? } else { self.config.clone() };
+        if config.quantization.is_some() {
+            return Err(anyhow!("{} est déjà quantifié", model_path.display()));
+        }
+        if config.architecture != Architecture::Custom {
+            bail!("L'inférence int8 ne couvre que l'architecture custom ({:?})", config.architecture);
+        }
+        if calibration_samples == 0 {
+            bail!("La calibration demande au moins une image");
+        }
+
+        let record = load_record(&model_path, &config, &self.device)?;
+        let model: MalariaCNN<B> = MalariaCNN::new(
+            &self.device,
+            config.image_channels,
+            &config.architecture,
+            &config.conv_blocks,
+            &config.fc_units,
+            config.num_classes,
+            config.dropout_rate,
+        )
+        .load_record(record);
+
+        // Same seed and ratio as training: calibrate on train images, measure on the held-out split
+        let full_dataset = MalariaDataset::new("data", config.preprocessor()?, false)?;
+        let (train_dataset, valid_dataset) = full_dataset.split(0.8);
+        let batcher = MalariaBatcher::<B>::new(full_dataset.preprocessor.clone());
+        let calibration = batches(&batcher, &train_dataset, calibration_samples, config.batch_size, &self.device);
+        let activation_ranges = calibrate(&model, calibration.map(|batch| batch.images))?;
+        let calibration_samples = calibration_samples.min(train_dataset.len());
+        println!("📏 Calibration sur {} images ({} couches)", calibration_samples, activation_ranges.len());
+
+        let scheme = QuantScheme::default()
+            .with_value(QuantValue::Q8S)
+            .with_level(QuantLevel::Tensor)
+            .with_param(QuantParam::F32)
+            .with_store(QuantStore::Native);
+        let mut weight_quantizer = WeightQuantizer(Quantizer { calibration: Calibration::MinMax, scheme });
+        let quantized = model.clone().map(&mut weight_quantizer);
+
+        let output = Path::new(output).with_extension("");
+        BinFileRecorder::<FullPrecisionSettings>::new()
+            .record(quantized.into_record(), output.clone())?;
+        let quantized_config =
+            ModelConfig { quantization: Some(QuantizationConfig { activation_ranges, calibration_samples }), ..config };
+        fs::write(output.with_extension("json"), serde_json::to_string_pretty(&quantized_config)?)?;
+
+        // Reloaded as the API does, so the int8 path measured is the one served; one image per
+        // forward pass, as in the API
+        let int8 = load_inference_model::<B>(&output, &quantized_config, &self.device)?;
+        let results = measure(
+            &[&InferenceModel::Float(model), &int8],
+            batches(&batcher, &valid_dataset, valid_dataset.len(), 1, &self.device),
+        );
+        let ((accuracy_f32, time_f32), (accuracy_int8, time_int8)) = (results[0], results[1]);
+        let per_image = |time: f64| time * 1000.0 / valid_dataset.len().max(1) as f64;
+
+        let size = |path: &Path| fs::metadata(path.with_extension("bin")).map(|m| m.len()).unwrap_or(0);
+        let (size_f32, size_int8) = (size(&model_path), size(&output));
+
+        println!("📊 Résultats sur {} images de validation:", valid_dataset.len());
+        println!("   - Précision f32: {:.2}%", accuracy_f32 * 100.0);
+        println!("   - Précision int8: {:.2}%", accuracy_int8 * 100.0);
+        println!("   - Écart: {:+.2} points", (accuracy_int8 - accuracy_f32) * 100.0);
+        println!(
+            "   - Latence par image: {:.2} ms (f32) → {:.2} ms (int8), accélération x{:.2}",
+            per_image(time_f32),
+            per_image(time_int8),
+            time_f32 / time_int8.max(f64::EPSILON)
+        );
+        println!(
+            "   - Taille: {:.1} Ko → {:.1} Ko",
+            size_f32 as f64 / 1024.0,
+            size_int8 as f64 / 1024.0
+        );
+        println!("💾 Modèle quantifié: {}", output.with_extension("bin").display());
+
+        Ok(())
+    }
+}
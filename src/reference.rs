@@ -0,0 +1,210 @@
+//! Reference backbones: ResNet-18 and MobileNetV2
+//!
+//! Both return a feature map; the classifier head is shared with the custom stack.
+
+use burn::{
+    module::Module,
+    nn::{
+        conv::{Conv2d, Conv2dConfig},
+        BatchNorm, BatchNormConfig, PaddingConfig2d,
+    },
+    tensor::{activation::relu, backend::Backend, module::max_pool2d, Tensor},
+};
+
+/// Convolution without bias (followed by BatchNorm), padding keeping the size at stride 1
+fn conv<B: Backend>(
+    device: &B::Device,
+    channels: [usize; 2],
+    kernel: usize,
+    stride: usize,
+    groups: usize,
+) -> Conv2d<B> {
+    Conv2dConfig::new(channels, [kernel, kernel])
+        .with_stride([stride, stride])
+        .with_padding(PaddingConfig2d::Explicit(kernel / 2, kernel / 2))
+        .with_groups(groups)
+        .with_bias(false)
+        .init(device)
+}
+
+/// Conv followed by BatchNorm
+#[derive(Module, Debug)]
+struct ConvBn<B: Backend> {
+    conv: Conv2d<B>,
+    norm: BatchNorm<B>,
+}
+
+impl<B: Backend> ConvBn<B> {
+    fn new(device: &B::Device, channels: [usize; 2], kernel: usize, stride: usize, groups: usize) -> Self {
+        Self {
+            conv: conv(device, channels, kernel, stride, groups),
+            norm: BatchNormConfig::new(channels[1]).init(device),
+        }
+    }
+
+    fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        self.norm.forward(self.conv.forward(x))
+    }
+}
+
+/// Two 3x3 convs with an identity (or 1x1 projection) shortcut
+#[derive(Module, Debug)]
+struct BasicBlock<B: Backend> {
+    conv1: ConvBn<B>,
+    conv2: ConvBn<B>,
+    downsample: Option<ConvBn<B>>,
+}
+
+impl<B: Backend> BasicBlock<B> {
+    fn new(device: &B::Device, in_channels: usize, out_channels: usize, stride: usize) -> Self {
+        let downsample = (in_channels != out_channels || stride != 1)
+            .then(|| ConvBn::new(device, [in_channels, out_channels], 1, stride, 1));
+        Self {
+            conv1: ConvBn::new(device, [in_channels, out_channels], 3, stride, 1),
+            conv2: ConvBn::new(device, [out_channels, out_channels], 3, 1, 1),
+            downsample,
+        }
+    }
+
+    fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        let shortcut = match &self.downsample {
+            Some(downsample) => downsample.forward(x.clone()),
+            None => x.clone(),
+        };
+        let y = self.conv2.forward(relu(self.conv1.forward(x)));
+        relu(y + shortcut)
+    }
+}
+
+/// ResNet-18: 7x7 stem and max pooling, then 4 stages of 2 basic blocks (stride 32 overall)
+#[derive(Module, Debug)]
+pub struct ResNet18<B: Backend> {
+    stem: ConvBn<B>,
+    blocks: Vec<BasicBlock<B>>,
+}
+
+impl<B: Backend> ResNet18<B> {
+    /// Stage widths are `base_width` × 1, 2, 4, 8 (64 in the original network)
+    pub fn new(device: &B::Device, in_channels: usize, base_width: usize) -> Self {
+        let stem = ConvBn::new(device, [in_channels, base_width], 7, 2, 1);
+        let mut channels = base_width;
+        let mut blocks = Vec::with_capacity(8);
+        for stage in 0..4 {
+            let out_channels = base_width << stage;
+            let stride = if stage == 0 { 1 } else { 2 };
+            blocks.push(BasicBlock::new(device, channels, out_channels, stride));
+            blocks.push(BasicBlock::new(device, out_channels, out_channels, 1));
+            channels = out_channels;
+        }
+        Self { stem, blocks }
+    }
+
+    pub fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        let x = relu(self.stem.forward(x));
+        let x = max_pool2d(x, [3, 3], [2, 2], [1, 1], [1, 1]);
+        self.blocks.iter().fold(x, |x, block| block.forward(x))
+    }
+}
+
+/// Channels of the last ResNet-18 stage
+pub fn resnet18_out_channels(base_width: usize) -> usize {
+    base_width * 8
+}
+
+fn relu6<B: Backend>(x: Tensor<B, 4>) -> Tensor<B, 4> {
+    x.clamp(0.0, 6.0)
+}
+
+/// 1x1 expansion, 3x3 depthwise conv and linear 1x1 projection, with a shortcut at stride 1
+#[derive(Module, Debug)]
+struct InvertedResidual<B: Backend> {
+    expand: Option<ConvBn<B>>,
+    depthwise: ConvBn<B>,
+    project: ConvBn<B>,
+    residual: bool,
+}
+
+impl<B: Backend> InvertedResidual<B> {
+    fn new(device: &B::Device, in_channels: usize, out_channels: usize, stride: usize, expansion: usize) -> Self {
+        let hidden = in_channels * expansion;
+        Self {
+            expand: (expansion != 1).then(|| ConvBn::new(device, [in_channels, hidden], 1, 1, 1)),
+            depthwise: ConvBn::new(device, [hidden, hidden], 3, stride, hidden),
+            project: ConvBn::new(device, [hidden, out_channels], 1, 1, 1),
+            residual: stride == 1 && in_channels == out_channels,
+        }
+    }
+
+    fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        let y = match &self.expand {
+            Some(expand) => relu6(expand.forward(x.clone())),
+            None => x.clone(),
+        };
+        let y = self.project.forward(relu6(self.depthwise.forward(y)));
+        if self.residual {
+            y + x
+        } else {
+            y
+        }
+    }
+}
+
+/// (expansion, channels, repeats, stride) of each MobileNetV2 stage
+const MOBILENET_V2_STAGES: [(usize, usize, usize, usize); 7] = [
+    (1, 16, 1, 1),
+    (6, 24, 2, 2),
+    (6, 32, 3, 2),
+    (6, 64, 4, 2),
+    (6, 96, 3, 1),
+    (6, 160, 3, 2),
+    (6, 320, 1, 1),
+];
+
+/// Channel count scaled by the width multiplier, rounded to a multiple of 8 (as in the paper)
+fn scaled_channels(channels: usize, width_multiplier: f32) -> usize {
+    let scaled = channels as f32 * width_multiplier;
+    let rounded = (((scaled + 4.0) as usize) / 8 * 8).max(8);
+    // Rounding down must not lose more than 10%
+    if (rounded as f32) < 0.9 * scaled {
+        rounded + 8
+    } else {
+        rounded
+    }
+}
+
+/// Channels of the MobileNetV2 head conv; 1280 only grows with multipliers above 1
+pub fn mobilenet_v2_out_channels(width_multiplier: f32) -> usize {
+    scaled_channels(1280, width_multiplier.max(1.0))
+}
+
+/// MobileNetV2: 3x3 stem, inverted residual stages with depthwise separable convs and a 1x1 head conv
+#[derive(Module, Debug)]
+pub struct MobileNetV2<B: Backend> {
+    stem: ConvBn<B>,
+    blocks: Vec<InvertedResidual<B>>,
+    head: ConvBn<B>,
+}
+
+impl<B: Backend> MobileNetV2<B> {
+    pub fn new(device: &B::Device, in_channels: usize, width_multiplier: f32) -> Self {
+        let mut channels = scaled_channels(32, width_multiplier);
+        let stem = ConvBn::new(device, [in_channels, channels], 3, 2, 1);
+        let mut blocks = Vec::new();
+        for (expansion, out_channels, repeats, stride) in MOBILENET_V2_STAGES {
+            let out_channels = scaled_channels(out_channels, width_multiplier);
+            for i in 0..repeats {
+                let stride = if i == 0 { stride } else { 1 };
+                blocks.push(InvertedResidual::new(device, channels, out_channels, stride, expansion));
+                channels = out_channels;
+            }
+        }
+        let head = ConvBn::new(device, [channels, mobilenet_v2_out_channels(width_multiplier)], 1, 1, 1);
+        Self { stem, blocks, head }
+    }
+
+    pub fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        let x = relu6(self.stem.forward(x));
+        let x = self.blocks.iter().fold(x, |x, block| block.forward(x));
+        relu6(self.head.forward(x))
+    }
+}
@@ -0,0 +1,92 @@
+//! Class-aware sampling of the training set for imbalanced data
+
+use std::sync::Mutex;
+
+use burn::data::dataset::Dataset;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use crate::data::{MalariaDataset, MalariaItem};
+
+/// Draws items class by class: position `i` yields an item of the `i % num_classes`-th class.
+///
+/// Each class is cycled through in a fresh random order, so minority items repeat
+/// while majority items are all seen before any of them comes back.
+pub struct ClassBalancedDataset {
+    dataset: MalariaDataset,
+    classes: Vec<Mutex<ClassCursor>>,
+    len: usize,
+}
+
+struct ClassCursor {
+    indices: Vec<usize>,
+    position: usize,
+    rng: StdRng,
+}
+
+impl ClassCursor {
+    fn next(&mut self) -> usize {
+        if self.position == self.indices.len() {
+            self.indices.shuffle(&mut self.rng);
+            self.position = 0;
+        }
+        self.position += 1;
+        self.indices[self.position - 1]
+    }
+}
+
+impl ClassBalancedDataset {
+    /// Every class drawn as often as the largest one; meant to be shuffled by the dataloader
+    pub fn oversampled(dataset: MalariaDataset) -> Self {
+        let classes = Self::cursors(&dataset);
+        let largest = classes
+            .iter()
+            .map(|cursor| cursor.lock().unwrap().indices.len())
+            .max()
+            .unwrap_or(0);
+        let len = largest * classes.len();
+        Self { dataset, classes, len }
+    }
+
+    /// Classes interleaved so that consecutive items, hence each batch, are balanced.
+    /// The dataloader must not shuffle it.
+    pub fn balanced_batches(dataset: MalariaDataset) -> Self {
+        let classes = Self::cursors(&dataset);
+        let len = dataset.len();
+        Self { dataset, classes, len }
+    }
+
+    /// One cursor per class present in the dataset, in label order
+    fn cursors(dataset: &MalariaDataset) -> Vec<Mutex<ClassCursor>> {
+        let num_labels = dataset.labels.iter().map(|&l| l as usize + 1).max().unwrap_or(0);
+        let mut by_label = vec![Vec::new(); num_labels];
+        for (index, &label) in dataset.labels.iter().enumerate() {
+            by_label[label as usize].push(index);
+        }
+        by_label
+            .into_iter()
+            .enumerate()
+            .filter(|(_, indices)| !indices.is_empty())
+            .map(|(label, indices)| {
+                Mutex::new(ClassCursor {
+                    position: indices.len(),
+                    indices,
+                    rng: StdRng::seed_from_u64(42 + label as u64),
+                })
+            })
+            .collect()
+    }
+}
+
+impl Dataset<MalariaItem> for ClassBalancedDataset {
+    fn get(&self, index: usize) -> Option<MalariaItem> {
+        if index >= self.len {
+            return None;
+        }
+        let cursor = &self.classes[index % self.classes.len()];
+        let item = cursor.lock().unwrap().next();
+        self.dataset.get(item)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
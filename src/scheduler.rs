@@ -0,0 +1,280 @@
+//! Learning-rate scheduler built from `ModelConfig`
+//!
+//! Burn's `LearnerBuilder` takes a single concrete `LrScheduler`, so the
+//! schedule chosen at runtime is wrapped in [`MalariaLrScheduler`].
+
+use std::f64::consts::PI;
+
+use anyhow::{anyhow, Result};
+use burn::{
+    lr_scheduler::{
+        constant::ConstantLr,
+        cosine::{CosineAnnealingLrScheduler, CosineAnnealingLrSchedulerConfig},
+        exponential::{ExponentialLrScheduler, ExponentialLrSchedulerConfig},
+        linear::{LinearLrScheduler, LinearLrSchedulerConfig},
+        step::{StepLrScheduler, StepLrSchedulerConfig},
+        LrScheduler,
+    },
+    optim::LearningRate,
+    tensor::backend::Backend,
+};
+use crate::config::{LrSchedule, ModelConfig};
+
+/// Schedule selected in `ModelConfig`, preceded by an optional linear warmup.
+///
+/// Stepped once per training iteration (batch), like every Burn scheduler.
+#[derive(Clone)]
+pub struct MalariaLrScheduler {
+    warmup: Option<LinearLrScheduler>,
+    warmup_iters: usize,
+    schedule: Schedule,
+    iteration: usize,
+    /// Fresh copies used to replay the schedule when loading a record
+    initial: (Option<LinearLrScheduler>, Schedule),
+}
+
+#[derive(Clone)]
+enum Schedule {
+    Constant(ConstantLr),
+    Step(StepLrScheduler),
+    Exponential(ExponentialLrScheduler),
+    Cosine(CosineAnnealingLrScheduler),
+    OneCycle(OneCycleLr),
+}
+
+impl MalariaLrScheduler {
+    /// Builds the scheduler, converting epoch-based settings with `iters_per_epoch`
+    pub fn new(config: &ModelConfig, iters_per_epoch: usize) -> Result<Self> {
+        let iters_per_epoch = iters_per_epoch.max(1);
+        let lr = config.learning_rate;
+        let warmup_iters = config.lr_warmup_epochs * iters_per_epoch;
+        let total_iters = config.num_epochs * iters_per_epoch;
+
+        let warmup = if warmup_iters > 0 {
+            Some(
+                LinearLrSchedulerConfig::new(lr / warmup_iters as f64, lr, warmup_iters)
+                    .init()
+                    .map_err(|e| anyhow!("Warmup invalide: {}", e))?,
+            )
+        } else {
+            None
+        };
+
+        let schedule = match config.lr_schedule {
+            LrSchedule::Constant => Schedule::Constant(ConstantLr::new(lr)),
+            LrSchedule::Step { step_epochs, gamma } => Schedule::Step(
+                StepLrSchedulerConfig::new(lr, (step_epochs * iters_per_epoch).max(1))
+                    .with_gamma(gamma)
+                    .init()
+                    .map_err(|e| anyhow!("Schedule step invalide: {}", e))?,
+            ),
+            LrSchedule::Exponential { gamma } => Schedule::Exponential(
+                // `gamma` is per epoch; Burn applies it per iteration
+                ExponentialLrSchedulerConfig::new(lr, gamma.powf(1.0 / iters_per_epoch as f64))
+                    .init()
+                    .map_err(|e| anyhow!("Schedule exponentiel invalide: {}", e))?,
+            ),
+            LrSchedule::CosineRestarts { period_epochs, min_lr } => Schedule::Cosine(
+                CosineAnnealingLrSchedulerConfig::new(lr, (period_epochs * iters_per_epoch).max(1))
+                    .with_min_lr(min_lr)
+                    .init()
+                    .map_err(|e| anyhow!("Schedule cosinus invalide: {}", e))?,
+            ),
+            LrSchedule::OneCycle { max_lr, pct_start, final_div_factor } => {
+                Schedule::OneCycle(OneCycleLr::new(
+                    lr,
+                    max_lr,
+                    total_iters.saturating_sub(warmup_iters).max(1),
+                    pct_start,
+                    final_div_factor,
+                )?)
+            }
+        };
+
+        Ok(Self {
+            warmup,
+            warmup_iters,
+            schedule: schedule.clone(),
+            iteration: 0,
+            initial: (warmup, schedule),
+        })
+    }
+}
+
+impl LrScheduler for MalariaLrScheduler {
+    /// Number of iterations already stepped; the state is replayed on load
+    type Record<B: Backend> = usize;
+
+    fn step(&mut self) -> LearningRate {
+        self.iteration += 1;
+        if let Some(warmup) = self.warmup.as_mut() {
+            if self.iteration <= self.warmup_iters {
+                return warmup.step();
+            }
+        }
+        match &mut self.schedule {
+            Schedule::Constant(s) => s.step(),
+            Schedule::Step(s) => s.step(),
+            Schedule::Exponential(s) => s.step(),
+            Schedule::Cosine(s) => s.step(),
+            Schedule::OneCycle(s) => s.step(),
+        }
+    }
+
+    fn to_record<B: Backend>(&self) -> Self::Record<B> {
+        self.iteration
+    }
+
+    fn load_record<B: Backend>(mut self, record: Self::Record<B>) -> Self {
+        let (warmup, schedule) = self.initial.clone();
+        self.warmup = warmup;
+        self.schedule = schedule;
+        self.iteration = 0;
+        for _ in 0..record {
+            self.step();
+        }
+        self
+    }
+}
+
+/// One-cycle policy: cosine ramp from `initial_lr` up to `max_lr` over the
+/// first `pct_start` of the run, then cosine annealing down to `initial_lr / final_div_factor`.
+#[derive(Clone, Debug)]
+struct OneCycleLr {
+    start_lr: LearningRate,
+    max_lr: LearningRate,
+    final_lr: LearningRate,
+    up_iters: usize,
+    down_iters: usize,
+    iteration: usize,
+}
+
+impl OneCycleLr {
+    fn new(
+        initial_lr: LearningRate,
+        max_lr: LearningRate,
+        total_iters: usize,
+        pct_start: f64,
+        final_div_factor: f64,
+    ) -> Result<Self> {
+        if max_lr < initial_lr {
+            return Err(anyhow!("One-cycle: max_lr ({}) < learning_rate ({})", max_lr, initial_lr));
+        }
+        if !(0.0..1.0).contains(&pct_start) {
+            return Err(anyhow!("One-cycle: pct_start doit être dans [0, 1)"));
+        }
+        if final_div_factor < 1.0 {
+            return Err(anyhow!("One-cycle: final_div_factor doit être >= 1"));
+        }
+        let up_iters = (total_iters as f64 * pct_start).round() as usize;
+        Ok(Self {
+            start_lr: initial_lr,
+            max_lr,
+            final_lr: initial_lr / final_div_factor,
+            up_iters,
+            down_iters: total_iters.saturating_sub(up_iters).max(1),
+            iteration: 0,
+        })
+    }
+
+    fn step(&mut self) -> LearningRate {
+        let i = self.iteration;
+        self.iteration += 1;
+        if i < self.up_iters {
+            cosine_interp(self.start_lr, self.max_lr, i as f64 / self.up_iters as f64)
+        } else {
+            let t = ((i - self.up_iters) as f64 / self.down_iters as f64).min(1.0);
+            cosine_interp(self.max_lr, self.final_lr, t)
+        }
+    }
+}
+
+/// Cosine interpolation from `from` (t = 0) to `to` (t = 1)
+fn cosine_interp(from: f64, to: f64, t: f64) -> f64 {
+    to + (from - to) * 0.5 * (1.0 + (PI * t).cos())
+}
+
+#[cfg(test)]
+mod tests {
+    use burn_ndarray::NdArray;
+
+    use super::*;
+
+    fn config(lr_schedule: LrSchedule, lr_warmup_epochs: usize) -> ModelConfig {
+        ModelConfig { learning_rate: 0.01, num_epochs: 10, lr_warmup_epochs, lr_schedule, ..ModelConfig::default() }
+    }
+
+    fn steps(scheduler: &mut MalariaLrScheduler, count: usize) -> Vec<f64> {
+        (0..count).map(|_| scheduler.step()).collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn warmup_ramps_linearly_to_the_learning_rate() {
+        let mut scheduler = MalariaLrScheduler::new(&config(LrSchedule::Constant, 2), 2).unwrap();
+        let lrs = steps(&mut scheduler, 6);
+        // 4 iterations from lr / 4 in steps of 3/16 lr, then the schedule itself
+        for (lr, expected) in lrs.iter().zip([0.0025, 0.004375, 0.00625, 0.008125, 0.01, 0.01]) {
+            assert_close(*lr, expected);
+        }
+    }
+
+    #[test]
+    fn step_and_exponential_gammas_are_per_epoch() {
+        let mut step = MalariaLrScheduler::new(&config(LrSchedule::Step { step_epochs: 1, gamma: 0.5 }, 0), 2).unwrap();
+        let lrs = steps(&mut step, 5);
+        for (lr, expected) in lrs.iter().zip([0.01, 0.01, 0.005, 0.005, 0.0025]) {
+            assert_close(*lr, expected);
+        }
+
+        let mut exponential = MalariaLrScheduler::new(&config(LrSchedule::Exponential { gamma: 0.25 }, 0), 2).unwrap();
+        let lrs = steps(&mut exponential, 3);
+        assert_close(lrs[2] / lrs[0], 0.25);
+    }
+
+    #[test]
+    fn one_cycle_peaks_then_anneals() {
+        let schedule = LrSchedule::OneCycle { max_lr: 0.1, pct_start: 0.3, final_div_factor: 10.0 };
+        let mut scheduler = MalariaLrScheduler::new(&config(schedule, 0), 1).unwrap();
+        let lrs = steps(&mut scheduler, 12);
+        // 3 iterations up from lr, 7 down to lr / 10, then held there
+        assert_close(lrs[0], 0.01);
+        assert_close(lrs[3], 0.1);
+        assert_close(lrs[10], 0.001);
+        assert_close(lrs[11], 0.001);
+        assert!(lrs[..4].windows(2).all(|w| w[0] < w[1]));
+        assert!(lrs[3..11].windows(2).all(|w| w[0] > w[1]));
+    }
+
+    #[test]
+    fn one_cycle_after_warmup_covers_the_remaining_iterations() {
+        let schedule = LrSchedule::OneCycle { max_lr: 0.1, pct_start: 0.5, final_div_factor: 1.0 };
+        let mut scheduler = MalariaLrScheduler::new(&config(schedule, 2), 1).unwrap();
+        let lrs = steps(&mut scheduler, 10);
+        // 2 warmup iterations, then 4 up and 4 down
+        assert_close(lrs[0], 0.005);
+        assert_close(lrs[1], 0.0075);
+        assert_close(lrs[2], 0.01);
+        assert_close(lrs[6], 0.1);
+    }
+
+    #[test]
+    fn loading_a_record_replays_the_schedule() {
+        let schedule = LrSchedule::CosineRestarts { period_epochs: 2, min_lr: 0.001 };
+        let mut scheduler = MalariaLrScheduler::new(&config(schedule.clone(), 1), 3).unwrap();
+        steps(&mut scheduler, 7);
+        let record = scheduler.to_record::<NdArray>();
+
+        let mut resumed = MalariaLrScheduler::new(&config(schedule, 1), 3).unwrap().load_record::<NdArray>(record);
+        assert_eq!(steps(&mut resumed, 5), steps(&mut scheduler, 5));
+    }
+
+    #[test]
+    fn one_cycle_rejects_a_peak_below_the_learning_rate() {
+        let schedule = LrSchedule::OneCycle { max_lr: 0.001, pct_start: 0.3, final_div_factor: 10.0 };
+        assert!(MalariaLrScheduler::new(&config(schedule, 0), 1).is_err());
+    }
+}
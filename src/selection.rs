@@ -0,0 +1,291 @@
+//! Early stopping and best-checkpoint selection on a validation metric
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+};
+
+use burn::train::{
+    checkpoint::{CheckpointingAction, CheckpointingStrategy},
+    metric::store::{Aggregate, EventStoreClient, Split},
+    EarlyStoppingStrategy,
+};
+use crate::config::SelectionMetric;
+
+/// Lookups of the selection metric in Burn's metric store
+trait StoredMetric {
+    /// Name under which Burn's metric is stored
+    fn metric_name(&self) -> &'static str;
+
+    /// Whether `value` beats `reference` by more than `min_delta`
+    fn improves(&self, value: f64, reference: f64, min_delta: f64) -> bool;
+
+    fn value(&self, epoch: usize, store: &EventStoreClient) -> Option<f64> {
+        store.find_metric(self.metric_name(), epoch, Aggregate::Mean, Split::Valid)
+    }
+
+    /// Values for every epoch up to `epoch`; earlier epochs come from the
+    /// run's metric logs, so this also covers epochs before a resume.
+    fn values_up_to(&self, epoch: usize, store: &EventStoreClient) -> Vec<(usize, f64)> {
+        (1..=epoch)
+            .filter_map(|e| self.value(e, store).map(|v| (e, v)))
+            .collect()
+    }
+}
+
+impl StoredMetric for SelectionMetric {
+    fn metric_name(&self) -> &'static str {
+        match self {
+            Self::ValidLoss => "Loss",
+            Self::ValidAccuracy => "Accuracy",
+        }
+    }
+
+    fn improves(&self, value: f64, reference: f64, min_delta: f64) -> bool {
+        match self {
+            Self::ValidLoss => value < reference - min_delta,
+            Self::ValidAccuracy => value > reference + min_delta,
+        }
+    }
+}
+
+/// Per-epoch values of the selection metric, shared with the trainer
+#[derive(Clone, Default)]
+pub struct MetricHistory(Arc<Mutex<Vec<(usize, f64)>>>);
+
+impl MetricHistory {
+    fn record(&self, epoch: usize, value: f64) {
+        let mut values = self.0.lock().unwrap();
+        values.retain(|(e, _)| *e != epoch);
+        values.push((epoch, value));
+    }
+
+    /// Best `(epoch, value)` seen so far; the earliest epoch wins ties
+    pub fn best(&self, metric: SelectionMetric) -> Option<(usize, f64)> {
+        let values = self.0.lock().unwrap();
+        values.iter().copied().fold(None, |best, (epoch, value)| match best {
+            Some((_, best_value)) if !metric.improves(value, best_value, 0.0) => best,
+            _ => Some((epoch, value)),
+        })
+    }
+}
+
+/// Keeps the checkpoints of the `keep_best` best epochs, plus the `keep_last` latest ones
+/// (at least one, so that an interrupted run can still be resumed).
+pub struct TopKCheckpointing {
+    metric: SelectionMetric,
+    keep_best: usize,
+    keep_last: usize,
+    history: MetricHistory,
+    saved: BTreeSet<usize>,
+}
+
+impl TopKCheckpointing {
+    /// `saved_epochs` are checkpoints already on disk (when resuming) that the policy may prune
+    pub fn new(
+        metric: SelectionMetric,
+        keep_best: usize,
+        keep_last: usize,
+        history: MetricHistory,
+        saved_epochs: Vec<usize>,
+    ) -> Self {
+        Self {
+            metric,
+            keep_best: keep_best.max(1),
+            keep_last: keep_last.max(1),
+            history,
+            saved: saved_epochs.into_iter().collect(),
+        }
+    }
+}
+
+impl CheckpointingStrategy for TopKCheckpointing {
+    fn checkpointing(&mut self, epoch: usize, store: &EventStoreClient) -> Vec<CheckpointingAction> {
+        let values = self.metric.values_up_to(epoch, store);
+        self.actions(epoch, values)
+    }
+}
+
+impl TopKCheckpointing {
+    /// Saves `epoch` and deletes the checkpoints that fell out of the policy, given the metric
+    /// `values` of the epochs logged so far
+    fn actions(&mut self, epoch: usize, values: Vec<(usize, f64)>) -> Vec<CheckpointingAction> {
+        for (e, value) in values {
+            self.history.record(e, value);
+        }
+
+        let mut ranked = self.history.0.lock().unwrap().clone();
+        ranked.sort_by(|a, b| match self.metric {
+            SelectionMetric::ValidLoss => a.1.total_cmp(&b.1),
+            SelectionMetric::ValidAccuracy => b.1.total_cmp(&a.1),
+        });
+        let mut keep: BTreeSet<usize> = ranked
+            .into_iter()
+            .take(self.keep_best)
+            .map(|(e, _)| e)
+            .collect();
+        keep.insert(epoch);
+        let recent = epoch.saturating_sub(self.keep_last - 1)..epoch;
+        keep.extend(recent.filter(|e| self.saved.contains(e)));
+
+        let mut actions = vec![CheckpointingAction::Save];
+        actions.extend(
+            self.saved
+                .difference(&keep)
+                .map(|e| CheckpointingAction::Delete(*e)),
+        );
+        self.saved = keep;
+        actions
+    }
+}
+
+/// Stops when the metric has not improved by more than `min_delta` for `patience` epochs
+#[derive(Clone)]
+pub struct PatienceEarlyStopping {
+    metric: SelectionMetric,
+    patience: usize,
+    min_delta: f64,
+    best: Option<(usize, f64)>,
+}
+
+impl PatienceEarlyStopping {
+    pub fn new(metric: SelectionMetric, patience: usize, min_delta: f64) -> Self {
+        Self {
+            metric,
+            patience,
+            min_delta,
+            best: None,
+        }
+    }
+}
+
+impl EarlyStoppingStrategy for PatienceEarlyStopping {
+    fn should_stop(&mut self, epoch: usize, store: &EventStoreClient) -> bool {
+        if self.best.is_none() {
+            // First call after a resume: start from the best epoch already logged
+            self.resume(self.metric.values_up_to(epoch.saturating_sub(1), store));
+        }
+        match self.metric.value(epoch, store) {
+            Some(value) => self.observe(epoch, value),
+            None => false,
+        }
+    }
+}
+
+impl PatienceEarlyStopping {
+    /// Takes the best of the `values` logged before a resume as the reference
+    fn resume(&mut self, values: Vec<(usize, f64)>) {
+        self.best = values.into_iter().fold(None, |best, (e, v)| match best {
+            Some((_, b)) if !self.metric.improves(v, b, self.min_delta) => best,
+            _ => Some((e, v)),
+        });
+    }
+
+    /// Whether training should stop after `epoch` reached `value`
+    fn observe(&mut self, epoch: usize, value: f64) -> bool {
+        match self.best {
+            Some((best_epoch, best_value)) if !self.metric.improves(value, best_value, self.min_delta) => {
+                let stop = epoch - best_epoch >= self.patience;
+                if stop {
+                    println!(
+                        "⏹️  Arrêt anticipé: pas d'amélioration depuis l'époque {} ({:?} = {:.4})",
+                        best_epoch, self.metric, best_value
+                    );
+                }
+                stop
+            }
+            _ => {
+                self.best = Some((epoch, value));
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn top_k(keep_best: usize, keep_last: usize, saved_epochs: Vec<usize>) -> TopKCheckpointing {
+        TopKCheckpointing::new(SelectionMetric::ValidLoss, keep_best, keep_last, MetricHistory::default(), saved_epochs)
+    }
+
+    /// Epochs `1..=losses.len()` with the given validation losses
+    fn logged(losses: &[f64]) -> Vec<(usize, f64)> {
+        losses.iter().enumerate().map(|(i, &loss)| (i + 1, loss)).collect()
+    }
+
+    #[test]
+    fn keeps_the_best_epochs_and_the_latest() {
+        let losses = [0.9, 0.5, 0.7, 0.4, 0.8];
+        let mut strategy = top_k(2, 1, Vec::new());
+        let mut deleted = Vec::new();
+        for epoch in 1..=losses.len() {
+            let actions = strategy.actions(epoch, logged(&losses[..epoch]));
+            assert_eq!(actions[0], CheckpointingAction::Save);
+            deleted.extend(actions[1..].iter().map(|action| match action {
+                CheckpointingAction::Delete(e) => *e,
+                CheckpointingAction::Save => panic!("saved twice"),
+            }));
+        }
+        // Epochs 2 and 4 are the best two, epoch 5 the latest
+        assert_eq!(deleted, vec![1, 3]);
+        assert_eq!(strategy.saved, BTreeSet::from([2, 4, 5]));
+        assert_eq!(strategy.history.best(SelectionMetric::ValidLoss), Some((4, 0.4)));
+    }
+
+    #[test]
+    fn keep_last_covers_recent_epochs() {
+        let mut strategy = top_k(1, 3, Vec::new());
+        for epoch in 1..=4 {
+            strategy.actions(epoch, logged(&[0.1, 0.5, 0.6, 0.7][..epoch]));
+        }
+        assert_eq!(strategy.saved, BTreeSet::from([1, 2, 3, 4]));
+        strategy.actions(5, logged(&[0.1, 0.5, 0.6, 0.7, 0.8]));
+        assert_eq!(strategy.saved, BTreeSet::from([1, 3, 4, 5]));
+    }
+
+    #[test]
+    fn prunes_checkpoints_found_on_resume() {
+        let mut strategy = top_k(1, 1, vec![1, 2, 3]);
+        let actions = strategy.actions(4, logged(&[0.5, 0.3, 0.6, 0.7]));
+        assert_eq!(actions, vec![CheckpointingAction::Save, CheckpointingAction::Delete(1), CheckpointingAction::Delete(3)]);
+    }
+
+    #[test]
+    fn accuracy_ranks_the_highest_first() {
+        let history = MetricHistory::default();
+        let mut strategy = TopKCheckpointing::new(SelectionMetric::ValidAccuracy, 1, 1, history.clone(), Vec::new());
+        strategy.actions(3, vec![(1, 0.8), (2, 0.9), (3, 0.9)]);
+        assert_eq!(strategy.saved, BTreeSet::from([2, 3]));
+        // Ties go to the earliest epoch
+        assert_eq!(history.best(SelectionMetric::ValidAccuracy), Some((2, 0.9)));
+    }
+
+    #[test]
+    fn stops_after_patience_epochs_without_improvement() {
+        let mut stopping = PatienceEarlyStopping::new(SelectionMetric::ValidLoss, 2, 0.0);
+        let stops: Vec<bool> = logged(&[0.5, 0.4, 0.45, 0.41, 0.42])
+            .into_iter()
+            .map(|(epoch, loss)| stopping.observe(epoch, loss))
+            .collect();
+        assert_eq!(stops, vec![false, false, false, true, true]);
+    }
+
+    #[test]
+    fn improvements_below_min_delta_do_not_count() {
+        let mut stopping = PatienceEarlyStopping::new(SelectionMetric::ValidAccuracy, 1, 0.01);
+        assert!(!stopping.observe(1, 0.90));
+        assert!(!stopping.observe(2, 0.92));
+        assert!(stopping.observe(3, 0.925));
+    }
+
+    #[test]
+    fn patience_resumes_from_the_best_logged_epoch() {
+        let mut stopping = PatienceEarlyStopping::new(SelectionMetric::ValidLoss, 3, 0.0);
+        stopping.resume(logged(&[0.5, 0.3, 0.4]));
+        assert_eq!(stopping.best, Some((2, 0.3)));
+        assert!(!stopping.observe(4, 0.35));
+        assert!(stopping.observe(5, 0.35));
+    }
+}
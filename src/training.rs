@@ -1,113 +1,576 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail};
 use burn::{
-    data::dataloader::DataLoaderBuilder,
-    optim::{AdamConfig, decay::WeightDecayConfig},
-    tensor::backend::AutodiffBackend,
+    data::{
+        dataloader::{DataLoader, DataLoaderBuilder},
+        dataset::Dataset,
+    },
+    grad_clipping::GradientClippingConfig,
+    optim::{
+        decay::WeightDecayConfig, momentum::MomentumConfig, AdamConfig, AdamWConfig, Optimizer,
+        RmsPropConfig, SgdConfig,
+    },
+    tensor::{
+        backend::{AutodiffBackend, Backend},
+        ElementConversion,
+    },
+    module::AutodiffModule,
     train::{
+        logger::{FileMetricLogger, MetricLogger},
         metric::{AccuracyMetric, LossMetric},
-        LearnerBuilder,
+        LearnerBuilder, ValidStep,
     },
     record::{BinFileRecorder, FullPrecisionSettings, Recorder},
     prelude::Module,
 };
 use crate::{
-    config::ModelConfig,
-    data::{MalariaBatcher, MalariaDataset},
+    averaging::{average_checkpoints, recompute_batch_norm, EmaOptimizer},
+    config::{GradClipping, ModelConfig, OptimizerConfig, Sampling, WeightAveraging},
+    cross_validation::{slide_id, stratified_folds},
+    data::{MalariaBatch, MalariaBatcher, MalariaDataset},
+    distillation::{load_teacher, DistillationBatcher},
+    fine_tune::{load_pretrained, ParamGroupOptimizer},
+    loss::ClassificationLoss,
     malaria_cnn::MalariaCNN,
+    preprocessing::{PixelNormalization, Preprocessor},
+    sampling::ClassBalancedDataset,
+    scheduler::MalariaLrScheduler,
+    selection::{MetricHistory, PatienceEarlyStopping, TopKCheckpointing},
 };
 
+/// Run directory holding Burn's logs and checkpoints; the final model is saved next to it
+pub const RUN_DIR: &str = "./malaria-model";
+
+/// Checkpoint to restart an interrupted run from
+#[derive(Debug, Clone, Copy)]
+pub enum ResumeFrom {
+    /// Most recent complete checkpoint in the run directory
+    Latest,
+    /// Checkpoint of the given epoch
+    Epoch(usize),
+}
+
 pub struct MalariaTrainer<B: AutodiffBackend> {
     config: ModelConfig,
     device: B::Device,
+    resume: Option<ResumeFrom>,
 }
 
 impl<B: AutodiffBackend> MalariaTrainer<B> {
     pub fn new(config: ModelConfig, device: B::Device) -> Self {
-        Self { config, device }
+        Self { config, device, resume: None }
+    }
+
+    /// Restores model, optimizer and scheduler state from a checkpoint before training
+    pub fn resume_from(mut self, resume: ResumeFrom) -> Self {
+        self.resume = Some(resume);
+        self
     }
 
     pub fn run(&self) -> anyhow::Result<()> {
         println!("🚀 Démarrage de l'entraînement sur GPU (WGPU)");
         println!("⚙️  Device: {:?}", self.device);
+
+        let checkpoint_dir = Path::new(RUN_DIR).join("checkpoint");
+        let saved_epochs = checkpoint_epochs(&checkpoint_dir);
+        let resume_epoch = match self.resume {
+            None => None,
+            Some(ResumeFrom::Latest) => Some(*saved_epochs.last().ok_or_else(|| {
+                anyhow!("Aucun checkpoint complet dans {}", checkpoint_dir.display())
+            })?),
+            Some(ResumeFrom::Epoch(epoch)) if saved_epochs.contains(&epoch) => Some(epoch),
+            Some(ResumeFrom::Epoch(epoch)) => bail!(
+                "Checkpoint de l'époque {} introuvable dans {} (disponibles: {:?})",
+                epoch,
+                checkpoint_dir.display(),
+                saved_epochs
+            ),
+        };
+        if let Some(epoch) = resume_epoch {
+            if epoch >= self.config.num_epochs {
+                bail!("L'époque {} est déjà la dernière (num_epochs = {})", epoch, self.config.num_epochs);
+            }
+            println!("⏯️  Reprise depuis le checkpoint de l'époque {}", epoch);
+        }
+        
+        let full_dataset = self.load_dataset()?;
+        
+        let (train_dataset, valid_dataset) = full_dataset.split(0.8);
         
+        let metrics = self.fit_split(train_dataset, valid_dataset, Path::new(RUN_DIR), resume_epoch)?;
+        println!("📊 Validation: loss {:.4}, précision {:.2}%", metrics.loss, metrics.accuracy);
+
+        Ok(())
+    }
+
+    /// Trains one model per fold of a K-fold split and reports mean and standard deviation
+    /// of the validation metrics. Fold runs live in `RUN_DIR/fold-K`.
+    pub fn cross_validate(&self, num_folds: usize, grouped: bool, keep_models: bool) -> anyhow::Result<()> {
+        if num_folds < 2 {
+            bail!("La validation croisée demande au moins 2 folds (reçu {})", num_folds);
+        }
+        println!(
+            "🔁 Validation croisée en {} folds{}",
+            num_folds,
+            if grouped { " (groupés par lame)" } else { "" }
+        );
+        println!("⚙️  Device: {:?}", self.device);
+
+        let dataset = self.load_dataset()?;
+        let groups = grouped.then(|| dataset.images.iter().map(|path| slide_id(path)).collect::<Vec<_>>());
+        let folds = stratified_folds(&dataset.labels, groups.as_deref(), num_folds);
+        if let Some(k) = folds.iter().position(|fold| fold.is_empty()) {
+            bail!("Le fold {} est vide: pas assez de lames pour {} folds", k + 1, num_folds);
+        }
+
+        let mut results = Vec::with_capacity(num_folds);
+        for (k, valid_indices) in folds.iter().enumerate() {
+            println!("\n📂 Fold {}/{}", k + 1, num_folds);
+            let mut in_valid = vec![false; dataset.len()];
+            for &i in valid_indices {
+                in_valid[i] = true;
+            }
+            let train_indices: Vec<usize> = (0..dataset.len()).filter(|&i| !in_valid[i]).collect();
+
+            let run_dir = Path::new(RUN_DIR).join(format!("fold-{}", k + 1));
+            let metrics = self.fit_split(
+                dataset.subset(&train_indices),
+                dataset.subset(valid_indices),
+                &run_dir,
+                None,
+            )?;
+            println!("📊 Fold {}: loss {:.4}, précision {:.2}%", k + 1, metrics.loss, metrics.accuracy);
+            if !keep_models {
+                fs::remove_file(run_dir.with_extension("bin"))?;
+                fs::remove_file(run_dir.with_extension("json"))?;
+                fs::remove_dir_all(run_dir.join("checkpoint"))?;
+            }
+            results.push(metrics);
+        }
+
+        let losses: Vec<f64> = results.iter().map(|m| m.loss).collect();
+        let accuracies: Vec<f64> = results.iter().map(|m| m.accuracy).collect();
+        let (loss_mean, loss_std) = mean_std(&losses);
+        let (accuracy_mean, accuracy_std) = mean_std(&accuracies);
+        println!("\n📊 Résultats sur {} folds:", num_folds);
+        println!("   - Loss: {:.4} ± {:.4}", loss_mean, loss_std);
+        println!("   - Précision: {:.2}% ± {:.2}", accuracy_mean, accuracy_std);
+        if keep_models {
+            println!("💾 Modèles conservés: {}/fold-{{1..{}}}.bin", RUN_DIR, num_folds);
+        }
+
+        Ok(())
+    }
+
+    fn load_dataset(&self) -> anyhow::Result<MalariaDataset> {
+        println!("📁 Chargement du dataset...");
+        MalariaDataset::new("data", dataset_preprocessor(&self.config)?, self.config.use_cache)
+    }
+
+    /// Trains on one train/valid split in `run_dir`, exports the selected epoch next to it
+    /// (`run_dir` + `.bin`) and returns that model's validation metrics
+    pub fn fit_split(
+        &self,
+        train_dataset: MalariaDataset,
+        valid_dataset: MalariaDataset,
+        run_dir: &Path,
+        resume_epoch: Option<usize>,
+    ) -> anyhow::Result<ValidMetrics> {
+        // Each Burn optimizer is a distinct type, hence one monomorphized training loop per variant
+        let clipping = self.config.grad_clipping.map(|clipping| match clipping {
+            GradClipping::Norm(max_norm) => GradientClippingConfig::Norm(max_norm),
+            GradClipping::Value(threshold) => GradientClippingConfig::Value(threshold),
+        });
+        match self.config.optimizer {
+            OptimizerConfig::Adam { beta_1, beta_2, epsilon, weight_decay } => self.fit(
+                AdamConfig::new()
+                    .with_beta_1(beta_1)
+                    .with_beta_2(beta_2)
+                    .with_epsilon(epsilon)
+                    .with_weight_decay(weight_decay_config(weight_decay))
+                    .with_grad_clipping(clipping)
+                    .init(),
+                train_dataset,
+                valid_dataset,
+                run_dir,
+                resume_epoch,
+            ),
+            OptimizerConfig::AdamW { beta_1, beta_2, epsilon, weight_decay } => self.fit(
+                AdamWConfig::new()
+                    .with_beta_1(beta_1)
+                    .with_beta_2(beta_2)
+                    .with_epsilon(epsilon)
+                    .with_weight_decay(weight_decay)
+                    .with_grad_clipping(clipping)
+                    .init(),
+                train_dataset,
+                valid_dataset,
+                run_dir,
+                resume_epoch,
+            ),
+            OptimizerConfig::Sgd { momentum, dampening, nesterov, weight_decay } => self.fit(
+                SgdConfig::new()
+                    .with_momentum((momentum > 0.0).then_some(MomentumConfig {
+                        momentum,
+                        dampening,
+                        nesterov,
+                    }))
+                    .with_weight_decay(weight_decay_config(weight_decay))
+                    .with_gradient_clipping(clipping)
+                    .init(),
+                train_dataset,
+                valid_dataset,
+                run_dir,
+                resume_epoch,
+            ),
+            OptimizerConfig::RmsProp { alpha, momentum, epsilon, centered, weight_decay } => self.fit(
+                RmsPropConfig::new()
+                    .with_alpha(alpha)
+                    .with_momentum(momentum)
+                    .with_epsilon(epsilon)
+                    .with_centered(centered)
+                    .with_weight_decay(weight_decay_config(weight_decay))
+                    .with_grad_clipping(clipping)
+                    .init(),
+                train_dataset,
+                valid_dataset,
+                run_dir,
+                resume_epoch,
+            ),
+        }
+    }
+
+    fn fit<O>(
+        &self,
+        optim: O,
+        train_dataset: MalariaDataset,
+        valid_dataset: MalariaDataset,
+        run_dir: &Path,
+        resume_epoch: Option<usize>,
+    ) -> anyhow::Result<ValidMetrics>
+    where
+        O: Optimizer<MalariaCNN<B>, B> + 'static,
+        O::Record: 'static,
+    {
+        self.config.validate()?;
+        let checkpoint_dir = run_dir.join("checkpoint");
+        // Saved with the weights: `normalization: dataset` becomes the statistics of this split
+        let config = resolve_normalization(&self.config, &train_dataset)?;
         let model: MalariaCNN<B> = MalariaCNN::new(
             &self.device,
             self.config.image_channels,
-            self.config.conv1_filters,
-            self.config.conv2_filters,
-            self.config.conv3_filters,
-            self.config.fc1_units,
-            self.config.fc2_units,
+            &self.config.architecture,
+            &self.config.conv_blocks,
+            &self.config.fc_units,
             self.config.num_classes,
             self.config.dropout_rate,
         );
+        // A resumed run gets its weights from the checkpoint instead
+        let model = match (&self.config.fine_tune, resume_epoch) {
+            (Some(fine_tune), None) => {
+                let model = load_pretrained(model, &self.config, fine_tune, &self.device)?;
+                println!("📥 Poids pré-entraînés: {}", fine_tune.pretrained_path);
+                model
+            }
+            _ => model,
+        };
         
         println!("✅ Modèle créé");
-        println!("📁 Chargement du dataset...");
-        
-        let full_dataset = MalariaDataset::new(
-            "data",
-            self.config.image_height,
-            self.config.image_width,
-            self.config.use_cache,
-        )?;
-        
-        let (train_dataset, valid_dataset) = full_dataset.split(0.8);
-        
+
         println!("📊 Dataset: {} train, {} valid", train_dataset.len(), valid_dataset.len());
+
+        let mut loss = ClassificationLoss::new(&self.config.loss, &train_dataset.labels, self.config.num_classes)?;
+        if let Some(weights) = loss.class_weights() {
+            println!("⚖️  Poids des classes: {:?}", weights);
+        }
+        // Only the training loss is distilled; validation keeps the loss against the labels
+        let teacher = match &self.config.distillation {
+            Some(distillation) => {
+                loss = loss.with_distillation(distillation.temperature, distillation.alpha)?;
+                Some(load_teacher::<B>(&config, distillation, &self.device)?)
+            }
+            None => None,
+        };
+        let model = model.with_loss(loss.clone());
         
         // ✅ FIX: Specify the Backend in DataLoaderBuilder
-        let batcher_train = MalariaBatcher::<B>::new(
-            self.config.image_height,
-            self.config.image_width,
-        );
+        let preprocessor = config.preprocessor()?;
+        let batcher_train = DistillationBatcher::new(MalariaBatcher::<B>::new(preprocessor.clone()), teacher);
         
-        let batcher_valid = MalariaBatcher::<B::InnerBackend>::new(
-            self.config.image_height,
-            self.config.image_width,
-        );
+        let batcher_valid = MalariaBatcher::<B::InnerBackend>::new(preprocessor);
         
         // ✅ FIX: DataLoaderBuilder expects the Backend as the first generic parameter
-        let dataloader_train = DataLoaderBuilder::<B, _, _>::new(batcher_train)
+        let mut builder_train = DataLoaderBuilder::<B, _, _>::new(batcher_train)
             .batch_size(self.config.batch_size)
-            .shuffle(42)
-            .num_workers(self.config.num_workers)
-            .build(train_dataset);
+            .num_workers(self.config.num_workers);
+        // Balanced batches rely on the order of the sampled items
+        if self.config.sampling != Sampling::BalancedBatches {
+            builder_train = builder_train.shuffle(42);
+        }
+        let (dataloader_train, train_dataset_len) = match self.config.sampling {
+            Sampling::Uniform => {
+                let len = train_dataset.len();
+                (builder_train.build(train_dataset), len)
+            }
+            Sampling::Oversample => {
+                let sampled = ClassBalancedDataset::oversampled(train_dataset);
+                let len = sampled.len();
+                (builder_train.build(sampled), len)
+            }
+            Sampling::BalancedBatches => {
+                let sampled = ClassBalancedDataset::balanced_batches(train_dataset);
+                let len = sampled.len();
+                (builder_train.build(sampled), len)
+            }
+        };
         
         let dataloader_valid = DataLoaderBuilder::<B::InnerBackend, _, _>::new(batcher_valid)
             .batch_size(self.config.batch_size)
             .num_workers(self.config.num_workers)
             .build(valid_dataset);
         
-        let optim = AdamConfig::new()
-            .with_weight_decay(Some(WeightDecayConfig::new(1e-4)));
+        let iters_per_epoch = train_dataset_len.div_ceil(self.config.batch_size);
+        let lr_scheduler = MalariaLrScheduler::new(&self.config, iters_per_epoch)?;
+        // Burn steps the optimizer once per `grad_accum_steps` iterations, dropping the remainder of an epoch
+        let steps_per_epoch = iters_per_epoch / self.config.grad_accum_steps.max(1);
+        let optim = ParamGroupOptimizer::new(optim, self.config.fine_tune.as_ref(), steps_per_epoch);
+        let (ema_decay, swa_epochs) = match self.config.weight_averaging {
+            None => (None, 1),
+            Some(WeightAveraging::Ema { decay }) if (0.0..1.0).contains(&decay) => (Some(decay), 1),
+            Some(WeightAveraging::Swa { epochs }) if epochs > 0 => (None, epochs),
+            Some(averaging) => bail!("Moyenne des poids invalide: {:?}", averaging),
+        };
+        let optim = EmaOptimizer::new(optim, &model, ema_decay);
+        let ema = optim.average();
         
         println!("⚡ Configuration:");
+        println!("   - Architecture: {:?}", self.config.architecture);
         println!("   - Époques: {}", self.config.num_epochs);
         println!("   - Batch size: {}", self.config.batch_size);
         println!("   - Learning rate: {}", self.config.learning_rate);
+        println!("   - Optimiseur: {:?}", self.config.optimizer);
+        if let Some(clipping) = self.config.grad_clipping {
+            println!("   - Clipping des gradients: {:?}", clipping);
+        }
+        if let Some(fine_tune) = &self.config.fine_tune {
+            println!(
+                "   - Fine-tuning: poids du backbone gelés {} époque(s), learning rate du backbone x{}",
+                fine_tune.freeze_backbone_epochs, fine_tune.backbone_lr_scale
+            );
+        }
+        println!("   - Loss: {:?}", self.config.loss);
+        if let Some(distillation) = &self.config.distillation {
+            println!(
+                "   - Distillation: température {}, alpha {}",
+                distillation.temperature, distillation.alpha
+            );
+        }
+        if let Some(averaging) = self.config.weight_averaging {
+            println!("   - Moyenne des poids: {:?}", averaging);
+        }
+        println!("   - Échantillonnage: {:?} ({} images par époque)", self.config.sampling, train_dataset_len);
+        println!("   - Schedule: {:?} (warmup: {} époque(s))", self.config.lr_schedule, self.config.lr_warmup_epochs);
         println!("   - Image size: {}x{}", self.config.image_width, self.config.image_height);
         println!("   - Cache: {}", self.config.use_cache);
         println!("🎯 Démarrage...");
         
-        let learner = LearnerBuilder::new("./malaria-model")
+        let history = MetricHistory::default();
+        // Burn's file loggers always start at epoch 1; a resumed run must append after the checkpoint
+        let mut logger_train = FileMetricLogger::new_train(run_dir.join("train"));
+        let mut logger_valid = FileMetricLogger::new_train(run_dir.join("valid"));
+        if let Some(epoch) = resume_epoch {
+            logger_train.end_epoch(epoch);
+            logger_valid.end_epoch(epoch);
+        }
+
+        let mut builder = LearnerBuilder::new(run_dir)
+            .metric_loggers(logger_train, logger_valid)
             .metric_train_numeric(LossMetric::new())
             .metric_valid_numeric(LossMetric::new())
             .metric_train_numeric(AccuracyMetric::new())
             .metric_valid_numeric(AccuracyMetric::new())
             .with_file_checkpointer(BinFileRecorder::<FullPrecisionSettings>::new())
+            .with_checkpointing_strategy(TopKCheckpointing::new(
+                self.config.selection_metric,
+                self.config.keep_best_checkpoints,
+                swa_epochs,
+                history.clone(),
+                if resume_epoch.is_some() { checkpoint_epochs(&checkpoint_dir) } else { Vec::new() },
+            ))
             .num_epochs(self.config.num_epochs)
             .grads_accumulation(self.config.grad_accum_steps)
-            .summary()
-            .build(model, optim.init(), self.config.learning_rate);
+            .summary();
+        if let Some(patience) = self.config.early_stopping_patience {
+            builder = builder.early_stopping(PatienceEarlyStopping::new(
+                self.config.selection_metric,
+                patience,
+                self.config.early_stopping_min_delta,
+            ));
+        }
+        if let Some(epoch) = resume_epoch {
+            builder = builder.checkpoint(epoch);
+        }
+        let learner = builder.build(model, optim, lr_scheduler);
         
-        let model_trained = learner.fit(dataloader_train, dataloader_valid);
+        let model_trained = learner.fit(dataloader_train.clone(), dataloader_valid.clone());
+        
+        // ✅ Export the best epoch rather than whatever the last one produced
+        let model = match history.best(self.config.selection_metric) {
+            Some((epoch, value)) => {
+                println!("🏆 Meilleure époque: {} ({:?} = {:.4})", epoch, self.config.selection_metric, value);
+                let record = BinFileRecorder::<FullPrecisionSettings>::new().load(
+                    checkpoint_dir.join(format!("model-{}", epoch)),
+                    &self.device,
+                )?;
+                model_trained.model.load_record(record)
+            }
+            None => model_trained.model,
+        };
+
+        let model = match self.config.weight_averaging {
+            None => model,
+            Some(averaging) => {
+                // A fresh model, so that BatchNorm statistics are not shared with the trained one
+                let fresh: MalariaCNN<B> = MalariaCNN::new(
+                    &self.device,
+                    self.config.image_channels,
+                    &self.config.architecture,
+                    &self.config.conv_blocks,
+                    &self.config.fc_units,
+                    self.config.num_classes,
+                    self.config.dropout_rate,
+                )
+                .with_loss(loss);
+                let averaged = match (averaging, &ema) {
+                    (WeightAveraging::Ema { .. }, Some(ema)) => ema.lock().unwrap().clone(),
+                    (WeightAveraging::Swa { epochs }, _) => {
+                        let saved = checkpoint_epochs(&checkpoint_dir);
+                        let last = saved.last().copied().unwrap_or(0);
+                        let averaged: Vec<usize> = saved.into_iter().filter(|&e| e + epochs > last).collect();
+                        println!("📉 SWA sur les époques {:?}", averaged);
+                        average_checkpoints(&fresh, &checkpoint_dir, &averaged, &self.device)?
+                    }
+                    (WeightAveraging::Ema { .. }, None) => unreachable!("the EMA is kept when configured"),
+                };
+                let averaged = fresh.load_record(averaged.into_record());
+                recompute_batch_norm(&averaged, dataloader_train.as_ref());
+                let averaged = averaged.valid();
+
+                let selected = ValidMetrics::compute(&model, dataloader_valid.as_ref());
+                let metrics = ValidMetrics::compute(&averaged, dataloader_valid.as_ref());
+                println!("📉 Moyenne des poids ({:?}):", averaging);
+                println!("   - Époque sélectionnée: loss {:.4}, précision {:.2}%", selected.loss, selected.accuracy);
+                println!("   - Poids moyennés: loss {:.4}, précision {:.2}%", metrics.loss, metrics.accuracy);
+                averaged
+            }
+        };
         
         println!("💾 Sauvegarde du modèle...");
         BinFileRecorder::<FullPrecisionSettings>::new()
-            .record(model_trained.model.into_record(), "./malaria-model".into())?;
+            .record(model.clone().into_record(), run_dir.to_path_buf())?;
+        // The config travels with the weights so that inference rebuilds the same architecture
+        fs::write(run_dir.with_extension("json"), serde_json::to_string_pretty(&config)?)?;
         
         println!("✅ Entraînement terminé!");
         
-        Ok(())
+        Ok(ValidMetrics::compute(&model, dataloader_valid.as_ref()))
+    }
+}
+
+/// Validation metrics of an exported model, averaged over every validation item
+#[derive(Debug, Clone, Copy)]
+pub struct ValidMetrics {
+    pub loss: f64,
+    /// In percent
+    pub accuracy: f64,
+}
+
+impl ValidMetrics {
+    fn compute<B: Backend>(model: &MalariaCNN<B>, dataloader: &dyn DataLoader<B, MalariaBatch<B>>) -> Self {
+        let mut loss_sum = 0.0;
+        let mut correct = 0;
+        let mut total = 0;
+        for batch in dataloader.iter() {
+            let output = ValidStep::step(model, batch);
+            let batch_size = output.targets.dims()[0];
+            loss_sum += output.loss.into_scalar().elem::<f64>() * batch_size as f64;
+            correct += output
+                .output
+                .argmax(1)
+                .reshape([batch_size])
+                .equal(output.targets)
+                .int()
+                .sum()
+                .into_scalar()
+                .elem::<i64>() as usize;
+            total += batch_size;
+        }
+        let total = total.max(1) as f64;
+        Self {
+            loss: loss_sum / total,
+            accuracy: correct as f64 / total * 100.0,
+        }
+    }
+}
+
+/// Preprocessor of the training datasets. With `normalization: dataset` the statistics are only
+/// known once the split is chosen, so the images are listed unnormalized
+pub fn dataset_preprocessor(config: &ModelConfig) -> anyhow::Result<Preprocessor> {
+    match config.preprocessing.normalization {
+        PixelNormalization::Dataset => Preprocessor::new(
+            config.image_height,
+            config.image_width,
+            config.image_channels,
+            &config.preprocessing.unnormalized(),
+        ),
+        _ => config.preprocessor(),
+    }
+}
+
+/// Replaces `normalization: dataset` by the per-channel mean and standard deviation of the
+/// training images
+fn resolve_normalization(config: &ModelConfig, train_dataset: &MalariaDataset) -> anyhow::Result<ModelConfig> {
+    let mut config = config.clone();
+    if config.preprocessing.normalization == PixelNormalization::Dataset {
+        println!("📐 Statistiques de normalisation sur {} images...", train_dataset.images.len());
+        let (mean, std) = dataset_preprocessor(&config)?.channel_statistics(&train_dataset.images)?;
+        println!("   - Moyenne: {:?}", mean);
+        println!("   - Écart-type: {:?}", std);
+        config.preprocessing.normalization = PixelNormalization::MeanStd { mean, std };
     }
-}
\ No newline at end of file
+    Ok(config)
+}
+
+/// Mean and sample standard deviation
+fn mean_std(values: &[f64]) -> (f64, f64) {
+    let n = values.len().max(1) as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+    (mean, variance.sqrt())
+}
+
+/// L2 penalty, `None` when zero
+fn weight_decay_config(penalty: f32) -> Option<WeightDecayConfig> {
+    (penalty > 0.0).then(|| WeightDecayConfig::new(penalty))
+}
+
+/// Epochs (sorted) for which model, optimizer and scheduler checkpoints all exist
+pub fn checkpoint_epochs(dir: &Path) -> Vec<usize> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut epochs: Vec<usize> = entries
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            name.strip_prefix("model-")?.strip_suffix(".bin")?.parse().ok()
+        })
+        .filter(|epoch| {
+            ["optim", "scheduler"]
+                .iter()
+                .all(|kind| dir.join(format!("{}-{}.bin", kind, epoch)).exists())
+        })
+        .collect();
+    epochs.sort_unstable();
+    epochs
+}
@@ -0,0 +1,144 @@
+//! Test-time augmentation on preprocessed CHW batches
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use burn::tensor::{backend::Backend, Tensor};
+use serde::Deserialize;
+
+/// Set of views evaluated for each image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TtaMode {
+    /// Identity, horizontal, vertical and both flips (4 views)
+    Flips,
+    /// All flips and 90-degree rotations (8 views, 4 for non-square inputs)
+    Dihedral,
+}
+
+impl FromStr for TtaMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "flips" => Ok(Self::Flips),
+            "dihedral" => Ok(Self::Dihedral),
+            other => Err(anyhow!("Mode TTA inconnu: {} (attendu: flips, dihedral)", other)),
+        }
+    }
+}
+
+impl TtaMode {
+    /// Stacks every view of `x` (`[batch, C, H, W]`) into one `[views * batch, C, H, W]` tensor.
+    ///
+    /// Views are laid out view-major; returns the tensor and the number of views.
+    pub fn augment<B: Backend>(&self, x: Tensor<B, 4>) -> (Tensor<B, 4>, usize) {
+        let [_, _, height, width] = x.dims();
+        let mut views = vec![
+            x.clone(),
+            x.clone().flip([3]),
+            x.clone().flip([2]),
+            x.clone().flip([2, 3]),
+        ];
+
+        // Rotations by 90/270 degrees swap H and W, so they only batch with square inputs
+        if *self == Self::Dihedral && height == width {
+            let transposed = x.swap_dims(2, 3);
+            views.push(transposed.clone());
+            views.push(transposed.clone().flip([3]));
+            views.push(transposed.clone().flip([2]));
+            views.push(transposed.flip([2, 3]));
+        }
+
+        let num_views = views.len();
+        (Tensor::cat(views, 0), num_views)
+    }
+}
+
+/// Averages a view-major `[views * batch, N]` tensor back to `[batch, N]`
+pub fn average_views<B: Backend>(x: Tensor<B, 2>, num_views: usize) -> Tensor<B, 2> {
+    let [rows, cols] = x.dims();
+    let batch_size = rows / num_views;
+    x.reshape([num_views, batch_size, cols])
+        .mean_dim(0)
+        .reshape([batch_size, cols])
+}
+
+#[cfg(test)]
+mod tests {
+    use burn_ndarray::NdArray;
+
+    use super::*;
+
+    /// `batch` single-channel images whose pixels are all distinct
+    fn images(batch: usize, height: usize, width: usize) -> Tensor<NdArray, 4> {
+        let pixels: Vec<f32> = (0..batch * height * width).map(|i| i as f32).collect();
+        Tensor::<NdArray, 1>::from_floats(pixels.as_slice(), &Default::default()).reshape([batch, 1, height, width])
+    }
+
+    fn values<const D: usize>(tensor: Tensor<NdArray, D>) -> Vec<f32> {
+        tensor.into_data().to_vec::<f32>().unwrap()
+    }
+
+    /// Pixels of each view, one entry per row of the augmented batch
+    fn views(augmented: Tensor<NdArray, 4>) -> Vec<Vec<f32>> {
+        let [rows, _, height, width] = augmented.dims();
+        values(augmented).chunks(height * width).take(rows).map(<[f32]>::to_vec).collect()
+    }
+
+    #[test]
+    fn flips_are_laid_out_view_major() {
+        let (augmented, num_views) = TtaMode::Flips.augment(images(2, 2, 2));
+        assert_eq!(num_views, 4);
+        assert_eq!(augmented.dims(), [8, 1, 2, 2]);
+        let views = views(augmented);
+        // Image 0 is [[0, 1], [2, 3]], image 1 is [[4, 5], [6, 7]]
+        assert_eq!(views[0], vec![0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(views[1], vec![4.0, 5.0, 6.0, 7.0]);
+        assert_eq!(views[2], vec![1.0, 0.0, 3.0, 2.0]);
+        assert_eq!(views[4], vec![2.0, 3.0, 0.0, 1.0]);
+        assert_eq!(views[6], vec![3.0, 2.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn dihedral_gives_eight_distinct_square_views() {
+        let (augmented, num_views) = TtaMode::Dihedral.augment(images(1, 3, 3));
+        assert_eq!(num_views, 8);
+        // Pixel values are small integers, so views compare exactly as integer lists
+        let views: Vec<Vec<u32>> = views(augmented).iter().map(|view| view.iter().map(|&p| p as u32).collect()).collect();
+        for view in &views {
+            let mut sorted = view.clone();
+            sorted.sort_unstable();
+            assert_eq!(sorted, (0..9).collect::<Vec<_>>(), "not a permutation of the image");
+        }
+        assert_eq!(views.iter().collect::<std::collections::BTreeSet<_>>().len(), 8);
+    }
+
+    #[test]
+    fn dihedral_falls_back_to_flips_on_non_square_inputs() {
+        let (augmented, num_views) = TtaMode::Dihedral.augment(images(1, 2, 3));
+        assert_eq!(num_views, 4);
+        assert_eq!(augmented.dims(), [4, 1, 2, 3]);
+    }
+
+    #[test]
+    fn average_views_means_each_image_over_its_views() {
+        // 3 views of 2 images with 2 outputs each
+        let rows = [[1.0, 0.0], [0.0, 1.0], [0.5, 0.5], [0.0, 1.0], [0.0, 1.0], [1.0, 0.0]];
+        let flat: Vec<f32> = rows.iter().flatten().copied().collect();
+        let x = Tensor::<NdArray, 1>::from_floats(flat.as_slice(), &Default::default()).reshape([6, 2]);
+        let averaged = average_views(x, 3);
+        assert_eq!(averaged.dims(), [2, 2]);
+        let expected = [0.5, 0.5, 1.0 / 3.0, 2.0 / 3.0];
+        for (actual, expected) in values(averaged).iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn mode_names_parse() {
+        assert_eq!("flips".parse::<TtaMode>().unwrap(), TtaMode::Flips);
+        assert_eq!("dihedral".parse::<TtaMode>().unwrap(), TtaMode::Dihedral);
+        assert!("rotations".parse::<TtaMode>().is_err());
+    }
+}
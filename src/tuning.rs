@@ -0,0 +1,450 @@
+//! Hyperparameter search over `ModelConfig` fields
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use burn::tensor::backend::AutodiffBackend;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::{
+    config::{ModelConfig, SelectionMetric},
+    data::MalariaDataset,
+    training::{checkpoint_epochs, dataset_preprocessor, MalariaTrainer, ValidMetrics, RUN_DIR},
+};
+
+/// How trial configurations are chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchStrategy {
+    /// Every combination of the listed values
+    Grid,
+    /// Independent samples from the search space
+    Random,
+    /// Random samples trained on a growing epoch budget, keeping the best half at each rung
+    Halving,
+}
+
+impl FromStr for SearchStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "grid" => Ok(Self::Grid),
+            "random" => Ok(Self::Random),
+            "halving" => Ok(Self::Halving),
+            other => Err(anyhow!("Stratégie inconnue: {} (attendu: grid, random, halving)", other)),
+        }
+    }
+}
+
+/// Values a `ModelConfig` field can take, keyed by field name in the search space file:
+///
+/// ```json
+/// {
+///   "fc_units": [[128, 64], [256]],
+///   "dropout_rate": { "uniform": [0.1, 0.5] },
+///   "learning_rate": { "log_uniform": [1e-4, 1e-2] }
+/// }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Domain {
+    /// Explicit values (any JSON, so enum fields like `optimizer` work too)
+    Choice(Vec<Value>),
+    Uniform { uniform: [f64; 2] },
+    LogUniform { log_uniform: [f64; 2] },
+}
+
+impl Domain {
+    fn sample(&self, rng: &mut StdRng, integer: bool) -> Value {
+        let x = match self {
+            Self::Choice(values) => return values[rng.random_range(0..values.len())].clone(),
+            Self::Uniform { uniform: [low, high] } => rng.random_range(*low..=*high),
+            Self::LogUniform { log_uniform: [low, high] } => {
+                rng.random_range(low.ln()..=high.ln()).exp()
+            }
+        };
+        if integer {
+            Value::from(x.round() as u64)
+        } else {
+            Value::from(x)
+        }
+    }
+}
+
+/// Search space file: field name to domain
+pub struct SearchSpace(BTreeMap<String, Domain>);
+
+impl SearchSpace {
+    /// Reads a search space over the fields of `base`
+    pub fn load(path: &Path, base: &ModelConfig) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Lecture de l'espace de recherche {}", path.display()))?;
+        let space = Self(
+            serde_json::from_str(&text)
+                .with_context(|| format!("Espace de recherche invalide: {}", path.display()))?,
+        );
+        space.validate(&serde_json::to_value(base)?)?;
+        Ok(space)
+    }
+
+    /// Rejects domains that cannot be sampled; integer fields of `base` need bounds ≥ 0
+    fn validate(&self, base: &Value) -> Result<()> {
+        for (field, domain) in &self.0 {
+            let (low, high) = match domain {
+                Domain::Choice(values) if values.is_empty() => bail!("Aucune valeur pour {}", field),
+                Domain::Choice(_) => continue,
+                Domain::Uniform { uniform: [low, high] } => (*low, *high),
+                Domain::LogUniform { log_uniform: [low, high] } => {
+                    if *low <= 0.0 {
+                        bail!("log_uniform demande des bornes > 0 pour {}", field);
+                    }
+                    (*low, *high)
+                }
+            };
+            if !low.is_finite() || !high.is_finite() {
+                bail!("Bornes non finies pour {}: [{}, {}]", field, low, high);
+            }
+            if low > high {
+                bail!("Bornes inversées pour {}: {} > {}", field, low, high);
+            }
+            if base.get(field).is_some_and(Value::is_u64) && low < 0.0 {
+                bail!("{} est un entier positif: bornes ≥ 0 attendues, pas {}", field, low);
+            }
+        }
+        Ok(())
+    }
+
+    /// Cartesian product of every `Choice` domain
+    fn grid(&self) -> Result<Vec<BTreeMap<String, Value>>> {
+        let mut points = vec![BTreeMap::new()];
+        for (field, domain) in &self.0 {
+            let Domain::Choice(values) = domain else {
+                bail!("La recherche grid demande des listes de valeurs ({} est un intervalle)", field);
+            };
+            points = points
+                .into_iter()
+                .flat_map(|point| {
+                    values.iter().map(move |value| {
+                        let mut point = point.clone();
+                        point.insert(field.clone(), value.clone());
+                        point
+                    })
+                })
+                .collect();
+        }
+        Ok(points)
+    }
+
+    fn sample(&self, base: &Value, rng: &mut StdRng) -> BTreeMap<String, Value> {
+        self.0
+            .iter()
+            .map(|(field, domain)| {
+                let integer = base.get(field).is_some_and(|v| v.is_u64());
+                (field.clone(), domain.sample(rng, integer))
+            })
+            .collect()
+    }
+}
+
+/// Overrides `base` fields with `point`; unknown fields and ill-typed values are errors
+fn apply(base: &ModelConfig, point: &BTreeMap<String, Value>) -> Result<ModelConfig> {
+    let mut value = serde_json::to_value(base)?;
+    let fields = value.as_object_mut().expect("ModelConfig is a JSON object");
+    for (field, v) in point {
+        match fields.get_mut(field) {
+            Some(slot) => *slot = v.clone(),
+            None => bail!("Champ inconnu dans l'espace de recherche: {}", field),
+        }
+    }
+    let config: ModelConfig =
+        serde_json::from_value(value).map_err(|e| anyhow!("Configuration invalide {:?}: {}", point, e))?;
+    config.validate().with_context(|| format!("Configuration invalide {:?}", point))?;
+    Ok(config)
+}
+
+/// One row of the leaderboard file
+#[derive(Debug, Clone, Serialize)]
+pub struct TrialResult {
+    pub trial: usize,
+    pub epochs: usize,
+    pub valid_loss: f64,
+    pub valid_accuracy: f64,
+    /// Searched fields only
+    pub params: BTreeMap<String, Value>,
+    pub config: ModelConfig,
+}
+
+pub struct MalariaTuner<B: AutodiffBackend> {
+    config: ModelConfig,
+    device: B::Device,
+    space: SearchSpace,
+    strategy: SearchStrategy,
+    trials: Option<usize>,
+    seed: u64,
+    /// Last dataset loaded, reused while trials keep the same image size
+    dataset: Option<MalariaDataset>,
+    results: Vec<TrialResult>,
+}
+
+impl<B: AutodiffBackend> MalariaTuner<B> {
+    /// `trials` caps the number of configurations (default: the whole grid, or 10 samples)
+    pub fn new(
+        config: ModelConfig,
+        device: B::Device,
+        space: SearchSpace,
+        strategy: SearchStrategy,
+        trials: Option<usize>,
+        seed: u64,
+    ) -> Self {
+        Self {
+            config,
+            device,
+            space,
+            strategy,
+            trials,
+            seed,
+            dataset: None,
+            results: Vec::new(),
+        }
+    }
+
+    pub fn run(mut self) -> Result<()> {
+        let tune_dir = Path::new(RUN_DIR).join("tune");
+        let leaderboard = tune_dir.join("leaderboard.json");
+        fs::create_dir_all(&tune_dir)?;
+        println!("🔬 Recherche d'hyperparamètres ({:?}) sur {:?}", self.strategy, self.device);
+
+        let base = serde_json::to_value(&self.config)?;
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let points = match self.strategy {
+            SearchStrategy::Grid => {
+                let mut grid = self.space.grid()?;
+                grid.truncate(self.trials.unwrap_or(grid.len()));
+                grid
+            }
+            SearchStrategy::Random | SearchStrategy::Halving => (0..self.trials.unwrap_or(10))
+                .map(|_| self.space.sample(&base, &mut rng))
+                .collect(),
+        };
+        if points.is_empty() {
+            bail!("Aucun essai à lancer");
+        }
+        let configs = points
+            .iter()
+            .map(|point| apply(&self.config, point))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut alive: Vec<usize> = (0..points.len()).collect();
+        let rungs = if self.strategy == SearchStrategy::Halving {
+            alive.len().ilog2() as usize + 1
+        } else {
+            1
+        };
+        for rung in 0..rungs {
+            if rungs > 1 {
+                println!("\n🪜 Palier {}/{}: {} essai(s)", rung + 1, rungs, alive.len());
+            }
+
+            let mut rung_results = Vec::with_capacity(alive.len());
+            for &trial in &alive {
+                // Epoch budget doubles at each rung and reaches the trial's `num_epochs` at the last one
+                let previous = (rung > 0).then(|| rung_epochs(&configs[trial], rungs, rung - 1));
+                let epochs = rung_epochs(&configs[trial], rungs, rung);
+                let run_dir = tune_dir.join(format!("trial-{}", trial + 1));
+                if previous == Some(epochs) {
+                    // Budget already reached at the previous rung
+                    let result = self.results.iter().find(|r| r.trial == trial + 1).expect("trial ran");
+                    let metrics = ValidMetrics { loss: result.valid_loss, accuracy: result.valid_accuracy };
+                    rung_results.push((trial, metrics));
+                    continue;
+                }
+                if previous.is_none() && run_dir.exists() {
+                    // Stale checkpoints from an earlier search must not be resumed
+                    fs::remove_dir_all(&run_dir)?;
+                }
+
+                let config = ModelConfig { num_epochs: epochs, ..configs[trial].clone() };
+                println!("\n🧪 Essai {} ({} époques): {}", trial + 1, epochs, Value::from_iter(points[trial].clone()));
+                // Later rungs continue from the last checkpoint of the previous one
+                let resume_epoch = previous.and_then(|_| checkpoint_epochs(&run_dir.join("checkpoint")).pop());
+                let metrics = self.run_trial(&config, &run_dir, resume_epoch)?;
+                println!("📊 Essai {}: loss {:.4}, précision {:.2}%", trial + 1, metrics.loss, metrics.accuracy);
+
+                self.results.retain(|r| r.trial != trial + 1);
+                self.results.push(TrialResult {
+                    trial: trial + 1,
+                    epochs,
+                    valid_loss: metrics.loss,
+                    valid_accuracy: metrics.accuracy,
+                    params: points[trial].clone(),
+                    config,
+                });
+                self.write_leaderboard(&leaderboard)?;
+                rung_results.push((trial, metrics));
+            }
+
+            rung_results.sort_by(|a, b| compare(self.config.selection_metric, &a.1, &b.1));
+            alive = rung_results
+                .into_iter()
+                .take(alive.len().div_ceil(2))
+                .map(|(trial, _)| trial)
+                .collect();
+        }
+
+        println!("\n🏆 Classement ({:?}):", self.config.selection_metric);
+        for (rank, result) in self.results.iter().take(10).enumerate() {
+            println!(
+                "   {:>2}. essai {:>3} — loss {:.4}, précision {:.2}% ({} époques) {}",
+                rank + 1,
+                result.trial,
+                result.valid_loss,
+                result.valid_accuracy,
+                result.epochs,
+                Value::from_iter(result.params.clone())
+            );
+        }
+        println!("💾 Classement complet: {}", leaderboard.display());
+
+        Ok(())
+    }
+
+    /// Trains a trial on the usual 80/20 split; `resume_epoch` continues a previous rung
+    fn run_trial(&mut self, config: &ModelConfig, run_dir: &Path, resume_epoch: Option<usize>) -> Result<ValidMetrics> {
+        let preprocessor = dataset_preprocessor(config)?;
+        let dataset = match self.dataset.take() {
+            Some(dataset) if dataset.preprocessor == preprocessor => dataset,
+            _ => MalariaDataset::new("data", preprocessor, config.use_cache)?,
+        };
+        let (train_dataset, valid_dataset) = dataset.split(0.8);
+        self.dataset = Some(dataset);
+
+        MalariaTrainer::<B>::new(config.clone(), self.device.clone())
+            .fit_split(train_dataset, valid_dataset, run_dir, resume_epoch)
+    }
+
+    /// Rewritten after every trial so that an interrupted search keeps its results
+    fn write_leaderboard(&mut self, path: &Path) -> Result<()> {
+        let metric = self.config.selection_metric;
+        self.results.sort_by(|a, b| {
+            // Trials that went further in a halving search rank first
+            b.epochs.cmp(&a.epochs).then_with(|| {
+                let a = ValidMetrics { loss: a.valid_loss, accuracy: a.valid_accuracy };
+                let b = ValidMetrics { loss: b.valid_loss, accuracy: b.valid_accuracy };
+                compare(metric, &a, &b)
+            })
+        });
+        fs::write(path, serde_json::to_string_pretty(&self.results)?)?;
+        Ok(())
+    }
+}
+
+fn rung_epochs(config: &ModelConfig, rungs: usize, rung: usize) -> usize {
+    (config.num_epochs >> (rungs - 1 - rung)).max(1)
+}
+
+/// Best first
+fn compare(metric: SelectionMetric, a: &ValidMetrics, b: &ValidMetrics) -> std::cmp::Ordering {
+    match metric {
+        SelectionMetric::ValidLoss => a.loss.total_cmp(&b.loss),
+        SelectionMetric::ValidAccuracy => b.accuracy.total_cmp(&a.accuracy),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn space(json: Value) -> SearchSpace {
+        SearchSpace(serde_json::from_value(json).unwrap())
+    }
+
+    fn base() -> Value {
+        serde_json::to_value(ModelConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn samples_stay_within_their_domain() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let choice = Domain::Choice(vec![json!("adam"), json!("sgd")]);
+        let uniform = Domain::Uniform { uniform: [0.1, 0.5] };
+        let log_uniform = Domain::LogUniform { log_uniform: [1e-4, 1e-2] };
+        for _ in 0..200 {
+            assert!(["adam", "sgd"].contains(&choice.sample(&mut rng, false).as_str().unwrap()));
+            assert!((0.1..=0.5).contains(&uniform.sample(&mut rng, false).as_f64().unwrap()));
+            assert!((1e-4..=1e-2).contains(&log_uniform.sample(&mut rng, false).as_f64().unwrap()));
+        }
+    }
+
+    #[test]
+    fn integer_fields_are_sampled_as_integers() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let batch_sizes = space(json!({ "batch_size": { "uniform": [2.0, 9.0] } }));
+        for _ in 0..50 {
+            let point = batch_sizes.sample(&base(), &mut rng);
+            let batch_size = point["batch_size"].as_u64().expect("an integer");
+            assert!((2..=9).contains(&batch_size));
+        }
+        let point = space(json!({ "dropout_rate": { "uniform": [0.1, 0.5] } })).sample(&base(), &mut rng);
+        assert!(point["dropout_rate"].is_f64());
+    }
+
+    #[test]
+    fn grid_is_the_product_of_the_choices() {
+        let grid = space(json!({ "batch_size": [8, 16], "dropout_rate": [0.1, 0.3, 0.5] })).grid().unwrap();
+        assert_eq!(grid.len(), 6);
+        for batch_size in [8, 16] {
+            for dropout_rate in [0.1, 0.3, 0.5] {
+                let point = BTreeMap::from([
+                    ("batch_size".to_string(), json!(batch_size)),
+                    ("dropout_rate".to_string(), json!(dropout_rate)),
+                ]);
+                assert!(grid.contains(&point), "{:?} missing", point);
+            }
+        }
+        assert!(space(json!({ "dropout_rate": { "uniform": [0.1, 0.5] } })).grid().is_err());
+    }
+
+    #[test]
+    fn rejects_unusable_bounds() {
+        let invalid = [
+            json!({ "dropout_rate": { "uniform": [0.5, 0.1] } }),
+            json!({ "learning_rate": { "log_uniform": [0.0, 1e-2] } }),
+            json!({ "batch_size": { "uniform": [-4.0, 16.0] } }),
+            json!({ "fc_units": [] }),
+        ];
+        for json in invalid {
+            assert!(space(json.clone()).validate(&base()).is_err(), "{} accepted", json);
+        }
+        let infinite = SearchSpace(BTreeMap::from([("dropout_rate".to_string(), Domain::Uniform { uniform: [0.0, f64::INFINITY] })]));
+        assert!(infinite.validate(&base()).is_err());
+        assert!(space(json!({ "dropout_rate": { "uniform": [0.1, 0.1] } })).validate(&base()).is_ok());
+    }
+
+    #[test]
+    fn points_override_known_fields_only() {
+        let point = BTreeMap::from([("batch_size".to_string(), json!(4))]);
+        assert_eq!(apply(&ModelConfig::default(), &point).unwrap().batch_size, 4);
+        let unknown = BTreeMap::from([("batch".to_string(), json!(4))]);
+        assert!(apply(&ModelConfig::default(), &unknown).is_err());
+        let ill_typed = BTreeMap::from([("batch_size".to_string(), json!("four"))]);
+        assert!(apply(&ModelConfig::default(), &ill_typed).is_err());
+        let no_blocks = BTreeMap::from([("conv_blocks".to_string(), json!([]))]);
+        assert!(apply(&ModelConfig::default(), &no_blocks).is_err());
+    }
+
+    #[test]
+    fn halving_rungs_double_the_epochs_up_to_the_full_budget() {
+        let config = ModelConfig { num_epochs: 12, ..ModelConfig::default() };
+        assert_eq!((0..3).map(|rung| rung_epochs(&config, 3, rung)).collect::<Vec<_>>(), vec![3, 6, 12]);
+        let short = ModelConfig { num_epochs: 1, ..ModelConfig::default() };
+        assert_eq!(rung_epochs(&short, 3, 0), 1);
+    }
+}
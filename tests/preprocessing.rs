@@ -0,0 +1,235 @@
+//! Golden outputs of the shared preprocessing: any change to the tensors fed to the model
+//! (and so to what a trained bundle expects) fails here.
+//!
+//! Regenerate the expected tensors after an intended change with
+//! `UPDATE_GOLDEN=1 cargo test --test preprocessing`.
+
+use std::path::{Path, PathBuf};
+
+use burn::data::dataloader::batcher::Batcher;
+use burn_ndarray::{NdArray, NdArrayDevice};
+use malaria_classifier::{
+    classifier::decode_image,
+    data::{MalariaBatcher, MalariaItem},
+    preprocessing::{
+        ChannelMode, PixelNormalization, PreprocessingConfig, ResizeFilter, ResizeStrategy, IMAGENET_MEAN, IMAGENET_STD,
+    },
+    ModelConfig, Preprocessor,
+};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/preprocessing").join(name)
+}
+
+fn preprocessor(height: usize, width: usize, channels: usize, config: PreprocessingConfig) -> Preprocessor {
+    Preprocessor::new(height, width, channels, &config).unwrap()
+}
+
+/// Compares bit for bit with `<golden>.json`, or rewrites it when `UPDATE_GOLDEN` is set
+fn assert_golden(golden: &str, image: &str, preprocessor: &Preprocessor) {
+    let actual = preprocessor.load(&fixture(image)).unwrap();
+    assert_eq!(actual.len(), preprocessor.image_size());
+
+    let path = fixture(&format!("{golden}.json"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, serde_json::to_string(&actual).unwrap()).unwrap();
+        return;
+    }
+    let expected: Vec<f32> = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(actual.len(), expected.len(), "{golden}: tensor size changed");
+    if let Some(i) = (0..actual.len()).find(|&i| actual[i].to_bits() != expected[i].to_bits()) {
+        panic!("{golden}: value {i} is {} instead of {}", actual[i], expected[i]);
+    }
+}
+
+#[test]
+fn rgb_default_preprocessing() {
+    let preprocessor = ModelConfig { image_height: 16, image_width: 16, ..ModelConfig::default() }.preprocessor().unwrap();
+    assert_golden("rgb_triangle_16x16", "cell_rgb.png", &preprocessor);
+}
+
+#[test]
+fn rgb_nearest_mean_std() {
+    let config = PreprocessingConfig {
+        filter: ResizeFilter::Nearest,
+        normalization: PixelNormalization::MeanStd { mean: vec![0.5, 0.4, 0.3], std: vec![0.25, 0.2, 0.15] },
+        ..PreprocessingConfig::default()
+    };
+    assert_golden("rgb_nearest_mean_std_12x20", "cell_rgb.png", &preprocessor(12, 20, 3, config));
+}
+
+#[test]
+fn grayscale_single_channel() {
+    let config = PreprocessingConfig { filter: ResizeFilter::CatmullRom, ..PreprocessingConfig::default() };
+    let preprocessor = preprocessor(16, 16, 1, config);
+    assert_golden("gray_catmull_rom_16x16", "cell_gray.png", &preprocessor);
+}
+
+#[test]
+fn rgb_to_single_channel() {
+    let config = PreprocessingConfig { filter: ResizeFilter::Lanczos3, ..PreprocessingConfig::default() };
+    let preprocessor = preprocessor(10, 14, 1, config);
+    assert_golden("rgb_luma_lanczos3_10x14", "cell_rgb.png", &preprocessor);
+}
+
+#[test]
+fn selected_channel() {
+    let config = PreprocessingConfig { channel_mode: ChannelMode::Select { index: 1 }, ..PreprocessingConfig::default() };
+    assert_golden("rgb_green_16x16", "cell_rgb.png", &preprocessor(16, 16, 1, config));
+}
+
+#[test]
+fn grayscale_repeated_on_rgb_channels() {
+    let config = PreprocessingConfig { channel_mode: ChannelMode::Grayscale, ..PreprocessingConfig::default() };
+    let rgb = preprocessor(16, 16, 3, config).load(&fixture("cell_rgb.png")).unwrap();
+    let gray = preprocessor(16, 16, 1, PreprocessingConfig::default()).load(&fixture("cell_rgb.png")).unwrap();
+    assert_eq!(rgb, [gray.as_slice(), &gray, &gray].concat());
+}
+
+#[test]
+fn letterbox() {
+    let config = PreprocessingConfig { resize: ResizeStrategy::Letterbox { pad_color: [255, 0, 128] }, ..PreprocessingConfig::default() };
+    let preprocessor = preprocessor(16, 16, 3, config);
+    assert_golden("rgb_letterbox_16x16", "cell_rgb.png", &preprocessor);
+
+    // The 37x29 image is fitted to 16x13: the first row is padding
+    let pixels = preprocessor.load(&fixture("cell_rgb.png")).unwrap();
+    assert_eq!([pixels[0], pixels[256], pixels[512]], [1.0, 0.0, 128.0 / 255.0]);
+}
+
+#[test]
+fn center_crop() {
+    // Taller than the crop (29 > 20) and narrower than it (37 < 40): cropped vertically, padded horizontally
+    let config = PreprocessingConfig { resize: ResizeStrategy::CenterCrop, ..PreprocessingConfig::default() };
+    assert_golden("rgb_center_crop_20x40", "cell_rgb.png", &preprocessor(20, 40, 3, config));
+}
+
+#[test]
+fn shorter_side_then_crop() {
+    let config = PreprocessingConfig {
+        resize: ResizeStrategy::ShorterSideCrop { shorter_side: 20 },
+        filter: ResizeFilter::CatmullRom,
+        ..PreprocessingConfig::default()
+    };
+    assert_golden("gray_shorter_side_crop_16x16", "cell_gray.png", &preprocessor(16, 16, 1, config));
+}
+
+#[test]
+fn training_batches_match_served_images() {
+    let config = PreprocessingConfig {
+        resize: ResizeStrategy::Letterbox { pad_color: [0, 0, 0] },
+        filter: ResizeFilter::Gaussian,
+        normalization: PixelNormalization::MeanStd { mean: vec![0.5, 0.4, 0.3], std: vec![0.25, 0.2, 0.15] },
+        ..PreprocessingConfig::default()
+    };
+    let preprocessor = preprocessor(24, 18, 3, config);
+    let images = ["cell_rgb.png", "cell_gray.png"];
+
+    // Training path: files read by the batcher
+    let items = images.iter().map(|name| MalariaItem { image_path: fixture(name).display().to_string(), label: 0 }).collect();
+    let batch = MalariaBatcher::<NdArray>::new(preprocessor.clone()).batch(items, &NdArrayDevice::Cpu);
+    assert_eq!(batch.images.dims(), [2, 3, 24, 18]);
+    let trained: Vec<f32> = batch.images.into_data().to_vec().unwrap();
+
+    // Serving path: uploaded bytes
+    let served: Vec<f32> = images
+        .iter()
+        .flat_map(|name| preprocessor.process(&decode_image(&std::fs::read(fixture(name)).unwrap()).unwrap()))
+        .collect();
+
+    assert_eq!(trained.len(), served.len());
+    assert!(trained.iter().zip(&served).all(|(a, b)| a.to_bits() == b.to_bits()));
+}
+
+#[test]
+fn dataset_statistics_standardize_the_images() {
+    let images = [fixture("cell_rgb.png"), fixture("cell_gray.png")];
+    let (mean, std) = preprocessor(16, 16, 3, PreprocessingConfig::default()).channel_statistics(&images).unwrap();
+
+    let config = PreprocessingConfig { normalization: PixelNormalization::MeanStd { mean, std }, ..PreprocessingConfig::default() };
+    let standardized = preprocessor(16, 16, 3, config);
+    let pixels: Vec<f32> = images.iter().flat_map(|path| standardized.load(path).unwrap()).collect();
+    for c in 0..3 {
+        let values: Vec<f64> = pixels.chunks_exact(256).skip(c).step_by(3).flatten().map(|&v| v as f64).collect();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+        assert!(mean.abs() < 1e-4, "channel {c}: mean {mean}");
+        assert!((variance.sqrt() - 1.0).abs() < 1e-4, "channel {c}: std {}", variance.sqrt());
+    }
+}
+
+#[test]
+fn imagenet_statistics() {
+    let imagenet = PreprocessingConfig { normalization: PixelNormalization::ImageNet, ..PreprocessingConfig::default() };
+    let explicit = PreprocessingConfig {
+        normalization: PixelNormalization::MeanStd { mean: IMAGENET_MEAN.to_vec(), std: IMAGENET_STD.to_vec() },
+        ..PreprocessingConfig::default()
+    };
+    assert_eq!(preprocessor(16, 16, 3, imagenet.clone()), preprocessor(16, 16, 3, explicit));
+    assert!(Preprocessor::new(16, 16, 1, &imagenet).is_err());
+}
+
+#[test]
+fn bundles_without_preprocessing_use_the_defaults() {
+    let mut bundle = serde_json::to_value(ModelConfig::default()).unwrap();
+    bundle.as_object_mut().unwrap().remove("preprocessing");
+    let config: ModelConfig = serde_json::from_value(bundle).unwrap();
+    assert_eq!(config.preprocessing, PreprocessingConfig::default());
+}
+
+#[test]
+fn rejects_invalid_configs() {
+    let config = PreprocessingConfig {
+        normalization: PixelNormalization::MeanStd { mean: vec![0.5], std: vec![0.25] },
+        ..PreprocessingConfig::default()
+    };
+    assert!(Preprocessor::new(16, 16, 3, &config).is_err());
+    assert!(Preprocessor::new(16, 16, 1, &config).is_ok());
+    assert!(Preprocessor::new(16, 16, 2, &PreprocessingConfig::default()).is_err());
+    let config = PreprocessingConfig { channel_mode: ChannelMode::Select { index: 3 }, ..PreprocessingConfig::default() };
+    assert!(Preprocessor::new(16, 16, 1, &config).is_err());
+    let config = PreprocessingConfig { resize: ResizeStrategy::ShorterSideCrop { shorter_side: 15 }, ..PreprocessingConfig::default() };
+    assert!(Preprocessor::new(16, 16, 3, &config).is_err());
+    // Training replaces it by the computed statistics before saving the bundle
+    let config = PreprocessingConfig { normalization: PixelNormalization::Dataset, ..PreprocessingConfig::default() };
+    assert!(Preprocessor::new(16, 16, 3, &config).is_err());
+}
+
+/// 16x16 map whose value is the row index
+fn row_map() -> Vec<f32> {
+    (0..256).map(|i| (i / 16) as f32).collect()
+}
+
+#[test]
+fn heatmaps_of_a_letterbox_bundle_line_up_with_the_upload() {
+    let mut bundle = serde_json::to_value(ModelConfig { image_height: 16, image_width: 16, ..ModelConfig::default() }).unwrap();
+    bundle["preprocessing"]["resize"] = serde_json::json!({ "type": "letterbox", "pad_color": [0, 0, 0] });
+    let config: ModelConfig = serde_json::from_value(bundle).unwrap();
+    let preprocessor = config.preprocessor().unwrap();
+
+    // A 32x16 upload is fitted to 16x8, with 4 rows of padding above and below
+    assert_eq!(preprocessor.placement(32, 16), [0.0, 4.0, 16.0, 8.0]);
+    let on_image = preprocessor.map_to_image(&row_map(), 16, (32, 16), 16, 8);
+    for (y, row) in on_image.chunks(16).enumerate() {
+        assert!(row.iter().all(|&value| value == Some(4.0 + y as f32)), "row {y}: {row:?}");
+    }
+}
+
+#[test]
+fn heatmaps_leave_out_what_was_cropped() {
+    let config = PreprocessingConfig { resize: ResizeStrategy::CenterCrop, ..PreprocessingConfig::default() };
+    let crop = preprocessor(16, 16, 3, config);
+
+    // The 16x32 upload loses 8 rows above and below
+    assert_eq!(crop.placement(16, 32), [0.0, -8.0, 16.0, 32.0]);
+    let on_image = crop.map_to_image(&row_map(), 16, (16, 32), 16, 32);
+    for (y, row) in on_image.chunks(16).enumerate() {
+        let expected = (8..24).contains(&y).then(|| (y - 8) as f32);
+        assert!(row.iter().all(|&value| value == expected), "row {y}: {row:?}");
+    }
+
+    // Stretched inputs cover the whole upload: the map comes back unchanged
+    let exact = preprocessor(16, 16, 3, PreprocessingConfig::default());
+    let on_image: Vec<f32> = exact.map_to_image(&row_map(), 16, (37, 29), 16, 16).into_iter().flatten().collect();
+    assert_eq!(on_image, row_map());
+}